@@ -0,0 +1,32 @@
+// How an image looks at a glance, all computed from a single decode of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Appearance {
+    // for clients to render while the image loads
+    blurhash: String,
+    // hex colors, most common first so the first is the dominant color
+    palette: Vec<String>,
+    // a perceptual hash, images that look alike differ in few bits
+    fingerprint: u64,
+}
+
+pub fn new(blurhash: String, palette: Vec<String>, fingerprint: u64) -> Appearance {
+    Appearance {
+        blurhash,
+        palette,
+        fingerprint,
+    }
+}
+
+impl Appearance {
+    pub fn blurhash(&self) -> String {
+        self.blurhash.clone()
+    }
+
+    pub fn palette(&self) -> Vec<String> {
+        self.palette.clone()
+    }
+
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+// s3 user metadata is a flat string map, all attributes share one json encoded entry
+const METADATA_KEY: &str = "attributes";
+
+// Facts derived from an object when it is uploaded, stored as its metadata so they
+// can be returned without reading the object again.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Attributes {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    blurhash: Option<String>,
+    // hex colors, most common first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    palette: Option<Vec<String>>,
+    // as displayed, i.e. after exif orientation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    // bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    // seconds, animations and video only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    frame_rate: Option<f64>,
+}
+
+pub fn new() -> Attributes {
+    Attributes::default()
+}
+
+impl Attributes {
+    pub fn with_blurhash(mut self, blurhash: String) -> Attributes {
+        self.blurhash = Some(blurhash);
+        self
+    }
+
+    pub fn with_palette(mut self, palette: Vec<String>) -> Attributes {
+        self.palette = Some(palette);
+        self
+    }
+
+    pub fn with_dimensions(mut self, width: u32, height: u32) -> Attributes {
+        self.width = Some(width);
+        self.height = Some(height);
+        self
+    }
+
+    pub fn with_size(mut self, size: u64) -> Attributes {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn with_duration(mut self, duration: f64) -> Attributes {
+        self.duration = Some(duration);
+        self
+    }
+
+    pub fn with_frame_rate(mut self, frame_rate: f64) -> Attributes {
+        self.frame_rate = Some(frame_rate);
+        self
+    }
+
+    pub fn blurhash(&self) -> Option<String> {
+        self.blurhash.clone()
+    }
+
+    pub fn palette(&self) -> Option<Vec<String>> {
+        self.palette.clone()
+    }
+
+    pub fn width(&self) -> Option<u32> {
+        self.width
+    }
+
+    pub fn height(&self) -> Option<u32> {
+        self.height
+    }
+
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    pub fn duration(&self) -> Option<f64> {
+        self.duration
+    }
+
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.frame_rate
+    }
+
+    pub fn to_metadata(&self) -> Result<HashMap<String, String>> {
+        let value = serde_json::to_string(self).context("could not serialize attributes")?;
+
+        Ok(HashMap::from([(String::from(METADATA_KEY), value)]))
+    }
+
+    // objects stored before an attribute existed simply don't have it
+    pub fn from_metadata(metadata: Option<&HashMap<String, String>>) -> Result<Attributes> {
+        match metadata.and_then(|metadata| metadata.get(METADATA_KEY)) {
+            Some(value) => serde_json::from_str(value).context("could not deserialize attributes"),
+            None => Ok(Attributes::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_metadata() {
+        let attributes = new()
+            .with_blurhash(String::from("LEHV6nWB2yk8pyo0adR*.7kCMdnj"))
+            .with_palette(vec![String::from("#1e2a3b"), String::from("#f0e0d0")])
+            .with_dimensions(640, 480)
+            .with_size(1024)
+            .with_duration(2.5)
+            .with_frame_rate(16.0);
+
+        let metadata = attributes.to_metadata().unwrap();
+
+        assert_eq!(
+            Attributes::from_metadata(Some(&metadata)).unwrap(),
+            attributes
+        );
+    }
+
+    #[test]
+    fn defaults_without_metadata() {
+        assert_eq!(Attributes::from_metadata(None).unwrap(), new());
+        assert_eq!(
+            Attributes::from_metadata(Some(&HashMap::new())).unwrap(),
+            new()
+        );
+    }
+}
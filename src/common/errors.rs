@@ -0,0 +1,78 @@
+use std::{error::Error, fmt};
+
+// a user supplied url with a disallowed scheme or one that resolves to a non public address
+#[derive(Debug)]
+pub struct ForbiddenUrl {
+    reason: String,
+}
+
+pub fn forbidden_url(reason: String) -> ForbiddenUrl {
+    ForbiddenUrl { reason }
+}
+
+impl fmt::Display for ForbiddenUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "forbidden url: {}", self.reason)
+    }
+}
+
+impl Error for ForbiddenUrl {}
+
+// media that is well formed but exceeds what we are willing to process
+#[derive(Debug)]
+pub struct MediaTooLarge {
+    reason: String,
+}
+
+pub fn media_too_large(reason: String) -> MediaTooLarge {
+    MediaTooLarge { reason }
+}
+
+impl fmt::Display for MediaTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "media too large: {}", self.reason)
+    }
+}
+
+impl Error for MediaTooLarge {}
+
+// media that could not be read at all
+#[derive(Debug)]
+pub struct InvalidMedia {
+    reason: String,
+}
+
+pub fn invalid_media(reason: String) -> InvalidMedia {
+    InvalidMedia { reason }
+}
+
+impl fmt::Display for InvalidMedia {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid media: {}", self.reason)
+    }
+}
+
+impl Error for InvalidMedia {}
+
+// too much work is already waiting, the caller should come back later
+#[derive(Debug)]
+pub struct Overloaded {
+    reason: String,
+}
+
+pub fn overloaded(reason: String) -> Overloaded {
+    Overloaded { reason }
+}
+
+impl fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "overloaded: {}", self.reason)
+    }
+}
+
+impl Error for Overloaded {}
+
+// finds a typed error anywhere in the context chain
+pub fn find<T: Error + 'static>(e: &anyhow::Error) -> Option<&T> {
+    e.chain().find_map(|cause| cause.downcast_ref::<T>())
+}
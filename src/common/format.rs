@@ -1,16 +1,28 @@
 use anyhow::{bail, Result};
+use serde::Deserialize;
 
-#[derive(Debug, Clone)]
+// set in the first byte of the vp8x flags for animated webp
+const WEBP_ANIMATION_FLAG: u8 = 0b0000_0010;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Format {
     Jpeg,
     Png,
     WebP,
     Gif,
     Mp4,
+    Avif,
+    Svg,
 }
 
 impl Format {
     pub fn infer(data: &[u8]) -> Result<Format> {
+        // svg is text so it has no magic bytes of its own, at best it matches as xml
+        if is_svg(data) {
+            return Ok(Format::Svg);
+        }
+
         match infer::get(data) {
             Some(kind) => match kind.extension() {
                 "jpeg" => Ok(Format::Jpeg),
@@ -32,6 +44,8 @@ impl Format {
             Format::WebP => String::from("image/webp"),
             Format::Gif => String::from("image/gif"),
             Format::Mp4 => String::from("video/mp4"),
+            Format::Avif => String::from("image/avif"),
+            Format::Svg => String::from("image/svg+xml"),
         }
     }
 
@@ -42,6 +56,66 @@ impl Format {
             Format::WebP => String::from("webp"),
             Format::Gif => String::from("gif"),
             Format::Mp4 => String::from("mp4"),
+            Format::Avif => String::from("avif"),
+            Format::Svg => String::from("svg"),
         }
     }
 }
+
+// Animated webp is flagged in the extended header, which is always the first chunk
+// when present. It is transcoded like gif rather than flattened to a still image.
+pub fn is_animated_webp(data: &[u8]) -> bool {
+    data.len() > 20
+        && &data[0..4] == b"RIFF"
+        && &data[8..12] == b"WEBP"
+        && &data[12..16] == b"VP8X"
+        && data[20] & WEBP_ANIMATION_FLAG != 0
+}
+
+fn is_svg(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(1024)];
+    let text = String::from_utf8_lossy(head);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+
+    if text.starts_with("<svg") {
+        return true;
+    }
+
+    // the root element may follow a prolog, comments or a doctype
+    (text.starts_with("<?xml") || text.starts_with("<!--") || text.starts_with("<!DOCTYPE svg"))
+        && text.contains("<svg")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infers_svg() {
+        let svgs: [&[u8]; 3] = [
+            b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+            b"\xef\xbb\xbf<?xml version=\"1.0\"?>\n<svg/>",
+            b"<!-- generated -->\n<svg></svg>",
+        ];
+
+        for svg in svgs {
+            assert_eq!(Format::infer(svg).unwrap(), Format::Svg);
+        }
+    }
+
+    #[test]
+    fn rejects_other_xml() {
+        assert!(Format::infer(b"<?xml version=\"1.0\"?><html/>").is_err());
+    }
+
+    #[test]
+    fn detects_animated_webp() {
+        let mut webp = b"RIFF\x24\0\0\0WEBPVP8X\x0a\0\0\0\x02\0\0\0\0\0\0\0\0\0".to_vec();
+        assert_eq!(Format::infer(&webp).unwrap(), Format::WebP);
+        assert!(is_animated_webp(&webp));
+
+        webp[20] = 0;
+        assert!(!is_animated_webp(&webp));
+        assert!(!is_animated_webp(b"RIFF\x24\0\0\0WEBPVP8 "));
+    }
+}
@@ -1,2 +1,7 @@
+pub mod appearance;
+pub mod attributes;
+pub mod errors;
 pub mod format;
+pub mod hash;
+pub mod profile;
 pub mod variant;
@@ -0,0 +1,223 @@
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+use super::format::Format;
+
+// how an image is fit into the profile's bounding box
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    // scale down to fit within the box, preserving aspect ratio
+    #[default]
+    Contain,
+    // scale and crop to fill the box, preserving aspect ratio
+    Cover,
+    // stretch to exactly the box
+    Fill,
+}
+
+impl Fit {
+    pub fn name(&self) -> String {
+        match self {
+            Fit::Contain => String::from("contain"),
+            Fit::Cover => String::from("cover"),
+            Fit::Fill => String::from("fill"),
+        }
+    }
+}
+
+// how animated inputs are transcoded to video for a profile
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct VideoOptions {
+    #[serde(default = "default_fps")]
+    fps: u32,
+    // x264 constant rate factor, lower is better quality
+    #[serde(default = "default_crf")]
+    crf: u8,
+    // output is cut off after this many seconds
+    #[serde(default = "default_max_duration")]
+    max_duration: u32,
+    // mp4, or webp for short loops that should play anywhere an image can
+    #[serde(default = "default_video_format")]
+    format: Format,
+}
+
+fn default_fps() -> u32 {
+    16
+}
+
+fn default_crf() -> u8 {
+    23
+}
+
+fn default_max_duration() -> u32 {
+    30
+}
+
+fn default_video_format() -> Format {
+    Format::Mp4
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        video_options(default_fps(), default_crf(), default_max_duration())
+    }
+}
+
+pub fn video_options(fps: u32, crf: u8, max_duration: u32) -> VideoOptions {
+    VideoOptions {
+        fps,
+        crf,
+        max_duration,
+        format: default_video_format(),
+    }
+}
+
+impl VideoOptions {
+    fn validate(&self) -> Result<()> {
+        if !(1..=60).contains(&self.fps) {
+            bail!("video fps must be between 1 and 60");
+        }
+        if self.crf > 51 {
+            bail!("video crf must be between 0 and 51");
+        }
+        if !(1..=300).contains(&self.max_duration) {
+            bail!("video max duration must be between 1 and 300 seconds");
+        }
+        if !matches!(self.format, Format::Mp4 | Format::WebP) {
+            bail!("unsupported video format {:?}", self.format);
+        }
+
+        Ok(())
+    }
+
+    pub fn fps(&self) -> u32 {
+        self.fps
+    }
+
+    pub fn crf(&self) -> u8 {
+        self.crf
+    }
+
+    pub fn max_duration(&self) -> u32 {
+        self.max_duration
+    }
+
+    pub fn format(&self) -> Format {
+        self.format.clone()
+    }
+}
+
+// name shared by every on the fly transform profile
+pub const TRANSFORM: &str = "transform";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct Profile {
+    name: String,
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    fit: Fit,
+    #[serde(default = "default_format")]
+    format: Format,
+    #[serde(default = "default_quality")]
+    quality: u8,
+    #[serde(default)]
+    video: VideoOptions,
+}
+
+fn default_format() -> Format {
+    Format::WebP
+}
+
+fn default_quality() -> u8 {
+    80
+}
+
+pub fn new(name: &str, width: u32, height: u32) -> Profile {
+    Profile {
+        name: name.to_string(),
+        width,
+        height,
+        fit: Fit::default(),
+        format: default_format(),
+        quality: default_quality(),
+        video: VideoOptions::default(),
+    }
+}
+
+pub fn transform(width: u32, height: u32, fit: Fit, format: Format, quality: u8) -> Profile {
+    Profile {
+        name: String::from(TRANSFORM),
+        width,
+        height,
+        fit,
+        format,
+        quality,
+        video: VideoOptions::default(),
+    }
+}
+
+impl Profile {
+    pub fn with_video(mut self, video: VideoOptions) -> Profile {
+        self.video = video;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.width == 0 || self.height == 0 {
+            bail!("width and height must be non zero");
+        }
+        if self.quality > 100 {
+            bail!("quality must be between 0 and 100");
+        }
+        if !matches!(
+            self.format,
+            Format::Jpeg | Format::Png | Format::WebP | Format::Avif
+        ) {
+            bail!("unsupported output format {:?}", self.format);
+        }
+
+        self.video.validate()
+    }
+
+    // uniquely identifies the output of this profile for a given input
+    pub fn signature(&self) -> String {
+        format!(
+            "{}x{}_{}_q{}.{}",
+            self.width,
+            self.height,
+            self.fit.name(),
+            self.quality,
+            self.format.extension()
+        )
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn fit(&self) -> Fit {
+        self.fit.clone()
+    }
+
+    pub fn format(&self) -> Format {
+        self.format.clone()
+    }
+
+    pub fn quality(&self) -> u8 {
+        self.quality
+    }
+
+    pub fn video(&self) -> VideoOptions {
+        self.video.clone()
+    }
+}
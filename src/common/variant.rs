@@ -1,6 +1,58 @@
-#[derive(Debug, Clone)]
+use super::{format::Format, profile::Profile};
+
+// appended to the profile name for its poster, so profile names may not end with it
+pub const POSTER_SUFFIX: &str = "_poster";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Variant {
-    Thumbnail,
     Original,
-    Avatar,
+    Profile(Profile),
+    // derived on request and cached, one per distinct set of parameters
+    Transform(Profile),
+    // still frame of an animated profile variant, shown before the video loads
+    Poster(Profile),
+}
+
+impl Variant {
+    pub fn name(&self) -> String {
+        match self {
+            Variant::Original => String::from("original"),
+            Variant::Profile(profile) | Variant::Transform(profile) => profile.name(),
+            Variant::Poster(profile) => format!("{}{}", profile.name(), POSTER_SUFFIX),
+        }
+    }
+
+    // only profile variants transcoded to mp4 get a poster, animated webp shows its
+    // first frame while loading like any other image
+    pub fn poster(&self) -> Option<Variant> {
+        match self {
+            Variant::Profile(profile) if profile.video().format() == Format::Mp4 => {
+                Some(Variant::Poster(profile.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    // storage key for the variant of a file, e.g. images/thumbnails/{file_name}
+    pub fn key(&self, file_name: &str) -> String {
+        match self {
+            Variant::Transform(profile) => format!(
+                "{}/{}",
+                Variant::transforms_prefix(file_name),
+                profile.signature()
+            ),
+            _ => format!("images/{}s/{}", self.name(), file_name),
+        }
+    }
+
+    // File names come from the request path and are joined into keys and paths, so
+    // anything that could step out of its directory is refused.
+    pub fn is_valid_file_name(file_name: &str) -> bool {
+        !file_name.is_empty() && !file_name.contains("..") && !file_name.contains(['/', '\\', '\0'])
+    }
+
+    // every cached transform of a file lives under this prefix
+    pub fn transforms_prefix(file_name: &str) -> String {
+        format!("images/transforms/{}", file_name)
+    }
 }
@@ -1,8 +1,15 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
+use anyhow::{Context, Result};
+
+use crate::gateways::pool::Pool;
 use crate::settings::Settings;
 use crate::usecases::clean_videos::CleanVideos;
+use crate::usecases::delete_image::DeleteImage;
+use crate::usecases::gateways::Storage;
 use crate::usecases::get_image::GetImage;
+use crate::usecases::similar_images::SimilarImages;
+use crate::usecases::transform_image::TransformImage;
 use crate::usecases::upload_avatar::UploadAvatar;
 use crate::usecases::upload_image::UploadImage;
 use crate::usecases::{self};
@@ -10,36 +17,80 @@ use crate::{gateways, settings};
 
 pub struct Container {
     pub settings: Arc<Settings>,
+    pub image_pool: Arc<Pool>,
+    pub video_pool: Arc<Pool>,
     pub upload_image: Arc<UploadImage>,
     pub upload_avatar: Arc<UploadAvatar>,
     pub get_image: Arc<GetImage>,
+    pub similar_images: Arc<SimilarImages>,
+    pub delete_image: Arc<DeleteImage>,
+    pub transform_image: Arc<TransformImage>,
     pub clean_videos: Arc<CleanVideos>,
 }
 
-pub async fn new() -> Container {
+pub async fn new() -> Result<Container> {
     let settings = Arc::new(settings::new());
-    let storage = Arc::new(gateways::s3::new(settings.clone()).await);
-    let images = Arc::new(gateways::images::new());
+    let storage: Arc<dyn Storage> = match settings.storage_backend().as_str() {
+        "s3" => Arc::new(gateways::s3::new(settings.clone()).await),
+        "fs" => Arc::new(gateways::fs::new(settings.clone())),
+        backend => panic!("unsupported storage backend: {}", backend),
+    };
+
+    with_storage(settings, storage)
+}
+
+// everything but the storage backend, which tests swap for a fake
+pub fn with_storage(settings: Arc<Settings>, storage: Arc<dyn Storage>) -> Result<Container> {
+    let image_pool = Arc::new(gateways::pool::new(settings.image_concurrency()));
+    let images = Arc::new(gateways::images::new(settings.clone(), image_pool.clone()));
     let web = Arc::new(gateways::http::new(settings.clone()));
-    let video = Arc::new(gateways::video::new());
+    let video_pool = Arc::new(gateways::pool::bounded(
+        settings.video_concurrency(),
+        settings.video_queue(),
+    ));
+    let video = Arc::new(gateways::video::new(video_pool.clone(), image_pool.clone()));
+    let index = Arc::new(
+        gateways::index::new(PathBuf::from(settings.index_path()))
+            .context("could not load index")?,
+    );
     let upload_image = Arc::new(usecases::upload_image::new(
         storage.clone(),
         images.clone(),
         video.clone(),
+        index.clone(),
     ));
     let get_image = Arc::new(usecases::get_image::new(storage.clone()));
+    let similar_images = Arc::new(usecases::similar_images::new(
+        index.clone(),
+        get_image.clone(),
+    ));
+    let delete_image = Arc::new(usecases::delete_image::new(
+        storage.clone(),
+        index.clone(),
+        settings.variants(),
+    ));
+    let transform_image = Arc::new(usecases::transform_image::new(
+        storage.clone(),
+        images.clone(),
+    ));
     let upload_avatar = Arc::new(usecases::upload_avatar::new(
         web.clone(),
         upload_image.clone(),
         get_image.clone(),
+        settings.variant(settings::AVATAR).unwrap(),
     ));
     let clean_videos = Arc::new(usecases::clean_videos::new(video.clone()));
 
-    Container {
+    Ok(Container {
         settings,
+        image_pool,
+        video_pool,
         upload_image,
         upload_avatar,
         get_image,
+        similar_images,
+        delete_image,
+        transform_image,
         clean_videos,
-    }
+    })
 }
@@ -1,8 +1,22 @@
-use crate::{common::variant::Variant, container::Container};
+use crate::{
+    common::{
+        errors::{self, ForbiddenUrl, InvalidMedia, MediaTooLarge, Overloaded},
+        format::Format,
+        profile::{self, Fit, Profile},
+        variant::Variant,
+    },
+    container::Container,
+    entities::image::Image,
+    gateways::pool::Stats,
+    settings,
+};
+use anyhow::{bail, Result};
 use axum::{
-    body::Bytes,
-    extract::{DefaultBodyLimit, Path, State},
-    http::{header, Request, StatusCode},
+    body::{Body, Bytes},
+    extract::{
+        multipart::MultipartError, DefaultBodyLimit, FromRequest, Multipart, Path, Query, State,
+    },
+    http::{header, HeaderName, Request, StatusCode},
     middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::{get, post, put},
@@ -14,6 +28,8 @@ use std::net::{Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use std::{any::Any, time::Duration};
 use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinSet;
+use tokio::time::{timeout_at, Instant};
 use tower::ServiceBuilder;
 use tower_http::{
     catch_panic::CatchPanicLayer, sensitive_headers::SetSensitiveRequestHeadersLayer,
@@ -24,15 +40,47 @@ use uuid::Uuid;
 
 const MAX_IMAGE_SIZE_BYTES: usize = 5 * 1024 * 1024;
 const MAX_REQUEST_DURATION_SECONDS: u64 = 30;
+// form uploads share the size limit and timeout of a single request
+const MAX_UPLOAD_FILES: usize = 10;
+// files still processing this close to the timeout are given up on, so the request
+// can still answer with the ones that finished
+const UPLOAD_DEADLINE_MARGIN_SECONDS: u64 = 2;
+const UPLOAD_ERROR: &str = "could not put image";
+const MAX_TRANSFORM_DIMENSION: u32 = 2048;
+// out of the 64 bits of a fingerprint
+const DEFAULT_SIMILAR_DISTANCE: u32 = 10;
+const MAX_SIMILAR_DISTANCE: u32 = 64;
+// roughly how long a queued transcode takes to clear
+const RETRY_AFTER_SECONDS: u64 = 10;
 
 pub async fn start(container: Arc<Container>) {
-    let app = Router::new()
+    let app = router(container);
+
+    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 8081));
+
+    info!("listening on {}", addr);
+
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap();
+}
+
+fn router(container: Arc<Container>) -> Router {
+    Router::new()
         .nest(
             "/v1",
             Router::new()
                 .route("/images", post(upload_image_route))
-                .route("/images/:file_name", get(get_image_route))
+                .route(
+                    "/images/:file_name",
+                    get(get_image_route).delete(delete_image_route),
+                )
+                .route("/images/:file_name/transform", get(transform_image_route))
+                .route("/images/:file_name/similar", get(similar_images_route))
                 .route("/avatars", put(upload_avatar_route))
+                .route("/stats", get(stats_route))
                 .layer(
                     ServiceBuilder::new()
                         .layer(SetSensitiveRequestHeadersLayer::new(once(
@@ -54,17 +102,7 @@ pub async fn start(container: Arc<Container>) {
                     MAX_REQUEST_DURATION_SECONDS,
                 ))),
         )
-        .with_state(container);
-
-    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 8081));
-
-    info!("listening on {}", addr);
-
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .unwrap();
+        .with_state(container)
 }
 
 async fn shutdown_signal() {
@@ -92,11 +130,11 @@ fn handle_panic(err: Box<dyn Any + Send + 'static>) -> Response {
 
     tracing::error!("caught panic: {}", details);
 
-    return (
+    (
         StatusCode::INTERNAL_SERVER_ERROR,
         Json(ErrorResponse { error: details }),
     )
-        .into_response();
+        .into_response()
 }
 
 // TODO: Get trace id from headers if present
@@ -133,29 +171,200 @@ async fn request_event<B>(request: Request<B>, next: Next<B>) -> Result<Response
 }
 
 async fn health_check_route() -> StatusCode {
-    return StatusCode::OK;
+    StatusCode::OK
 }
 
-async fn upload_image_route(State(container): State<Arc<Container>>, body: Bytes) -> Response {
-    let file_name = Uuid::new_v4();
+async fn upload_image_route(
+    State(container): State<Arc<Container>>,
+    Query(query): Query<VariantQuery>,
+    request: Request<Body>,
+) -> Response {
+    let variant = match get_variant(&container, query) {
+        Some(variant) => variant,
+        None => return unknown_variant_response(),
+    };
 
-    return match container
-        .upload_image
-        .execute(file_name.to_string(), body.as_ref(), Variant::Thumbnail)
-        .await
-    {
-        Ok(image) => (StatusCode::CREATED, Json(image)).into_response(),
+    if is_multipart(&request) {
+        return match Multipart::from_request(request, &container).await {
+            Ok(multipart) => upload_images(container.clone(), multipart, variant).await,
+            Err(rejection) => rejection.into_response(),
+        };
+    }
+
+    let body = match Bytes::from_request(request, &container).await {
+        Ok(body) => body,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    return match upload_image(&container, &body, variant).await {
+        Ok((status, image)) => (status, Json(image)).into_response(),
         Err(e) => {
-            tracing::warn!("could not put image: {}", e);
-            (
+            tracing::warn!("{}: {}", UPLOAD_ERROR, e);
+            error_response(&e, StatusCode::BAD_REQUEST, UPLOAD_ERROR)
+        }
+    };
+}
+
+// Form uploads carry one image per file part, other fields are ignored. Files are
+// processed concurrently and each gets a result in the order of the parts, with the
+// status and error it would have had uploaded alone, so a bad file doesn't fail the
+// others. 201 when every file was stored, 207 otherwise.
+async fn upload_images(
+    container: Arc<Container>,
+    mut multipart: Multipart,
+    variant: Variant,
+) -> Response {
+    let deadline = Instant::now()
+        + Duration::from_secs(MAX_REQUEST_DURATION_SECONDS - UPLOAD_DEADLINE_MARGIN_SECONDS);
+    let mut files = Vec::new();
+
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return multipart_error_response(e),
+        };
+
+        if field.file_name().is_none() {
+            continue;
+        }
+
+        if files.len() == MAX_UPLOAD_FILES {
+            return (
                 StatusCode::BAD_REQUEST,
                 Json(ErrorResponse {
-                    error: String::from("could not put image"),
+                    error: format!("at most {} files can be uploaded at once", MAX_UPLOAD_FILES),
                 }),
             )
-                .into_response()
+                .into_response();
+        }
+
+        match field.bytes().await {
+            Ok(data) => files.push(data),
+            Err(e) => return multipart_error_response(e),
+        };
+    }
+
+    if files.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: String::from("no files to upload"),
+            }),
+        )
+            .into_response();
+    }
+
+    // the set aborts whatever is still running once dropped, like a single upload
+    // is dropped by the timeout layer
+    let count = files.len();
+    let mut tasks = JoinSet::new();
+    for (i, data) in files.into_iter().enumerate() {
+        let container = container.clone();
+        let variant = variant.clone();
+        tasks.spawn(async move { (i, upload_image(&container, &data, variant).await) });
+    }
+
+    let mut statuses = vec![None; count];
+    let mut images = vec![None; count];
+    // what the timeout layer would have answered a single upload with, a task that
+    // panicked instead is what the panic layer would have answered
+    let unfinished = loop {
+        match timeout_at(deadline, tasks.join_next()).await {
+            Ok(Some(Ok((i, Ok((_, image)))))) => images[i] = Some(image),
+            Ok(Some(Ok((i, Err(e))))) => {
+                tracing::warn!("{}: {}", UPLOAD_ERROR, e);
+                statuses[i] = Some(error_status(&e, StatusCode::BAD_REQUEST));
+            }
+            Ok(Some(Err(e))) => tracing::warn!("{}: {}", UPLOAD_ERROR, e),
+            Ok(None) => break StatusCode::INTERNAL_SERVER_ERROR,
+            Err(_) => {
+                tracing::warn!("{}: out of time", UPLOAD_ERROR);
+                break StatusCode::REQUEST_TIMEOUT;
+            }
         }
     };
+
+    let results = images
+        .into_iter()
+        .zip(statuses)
+        .map(|(image, status)| match image {
+            Some(image) => UploadResult::Image(Box::new(image)),
+            None => UploadResult::Error(UploadError {
+                status: status.unwrap_or(unfinished).as_u16(),
+                error: String::from(UPLOAD_ERROR),
+            }),
+        })
+        .collect::<Vec<_>>();
+
+    let overloaded = results.iter().any(|result| match result {
+        UploadResult::Error(error) => error.status == StatusCode::SERVICE_UNAVAILABLE,
+        UploadResult::Image(_) => false,
+    });
+    let status = match results
+        .iter()
+        .all(|result| matches!(result, UploadResult::Image(_)))
+    {
+        true => StatusCode::CREATED,
+        false => StatusCode::MULTI_STATUS,
+    };
+
+    match overloaded {
+        true => (status, retry_after(), Json(results)).into_response(),
+        false => (status, Json(results)).into_response(),
+    }
+}
+
+// 201 for a new image, 200 when a content addressed upload was already stored
+async fn upload_image(
+    container: &Container,
+    data: &[u8],
+    variant: Variant,
+) -> Result<(StatusCode, Image)> {
+    if !container.settings.content_addressed() {
+        let image = container
+            .upload_image
+            .execute(Uuid::new_v4().to_string(), data, variant)
+            .await?;
+
+        return Ok((StatusCode::CREATED, image));
+    }
+
+    let (image, created) = container
+        .upload_image
+        .execute_deduplicated(data, variant)
+        .await?;
+
+    match created {
+        true => Ok((StatusCode::CREATED, image)),
+        false => Ok((StatusCode::OK, image)),
+    }
+}
+
+fn is_multipart<B>(request: &Request<B>) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(|content_type| {
+            content_type
+                .trim_start()
+                .to_ascii_lowercase()
+                .starts_with("multipart/form-data")
+        })
+        .unwrap_or(false)
+}
+
+fn multipart_error_response(e: MultipartError) -> Response {
+    tracing::warn!("could not read multipart body: {}", e);
+
+    (
+        e.status(),
+        Json(ErrorResponse {
+            error: String::from("could not read multipart body"),
+        }),
+    )
+        .into_response()
 }
 
 async fn upload_avatar_route(
@@ -166,10 +375,36 @@ async fn upload_avatar_route(
         Ok(avatar) => (StatusCode::CREATED, Json(avatar)).into_response(),
         Err(e) => {
             tracing::warn!("could not put avatar: {}", e);
+            error_response(&e, StatusCode::BAD_REQUEST, "could not put avatar")
+        }
+    };
+}
+
+async fn get_image_route(
+    State(container): State<Arc<Container>>,
+    Path(file_name): Path<String>,
+    Query(query): Query<VariantQuery>,
+) -> Response {
+    if !Variant::is_valid_file_name(&file_name) {
+        return invalid_file_name_response();
+    }
+
+    let variant = match get_variant(&container, query) {
+        Some(variant) => variant,
+        None => return unknown_variant_response(),
+    };
+
+    return match container.get_image.execute(file_name, variant).await {
+        Ok(image) => match image {
+            Some(image) => (StatusCode::OK, Json(image)).into_response(),
+            None => (StatusCode::NOT_FOUND).into_response(),
+        },
+        Err(e) => {
+            tracing::warn!("could not get image exists: {}", e);
             (
                 StatusCode::BAD_REQUEST,
                 Json(ErrorResponse {
-                    error: String::from("could not put avatar"),
+                    error: String::from("could not get image exists"),
                 }),
             )
                 .into_response()
@@ -177,30 +412,240 @@ async fn upload_avatar_route(
     };
 }
 
-async fn get_image_route(
+async fn similar_images_route(
     State(container): State<Arc<Container>>,
     Path(file_name): Path<String>,
+    Query(query): Query<SimilarQuery>,
 ) -> Response {
+    if !Variant::is_valid_file_name(&file_name) {
+        return invalid_file_name_response();
+    }
+
+    let distance = query.distance.unwrap_or(DEFAULT_SIMILAR_DISTANCE);
+
+    if distance > MAX_SIMILAR_DISTANCE {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("distance must be at most {}", MAX_SIMILAR_DISTANCE),
+            }),
+        )
+            .into_response();
+    }
+
+    let variant = match get_variant(
+        &container,
+        VariantQuery {
+            variant: query.variant,
+        },
+    ) {
+        Some(variant) => variant,
+        None => return unknown_variant_response(),
+    };
+
     return match container
-        .get_image
-        .execute(file_name, Variant::Thumbnail)
+        .similar_images
+        .execute(file_name, variant, distance)
         .await
     {
-        Ok(image) => match image {
-            Some(image) => (StatusCode::OK, Json(image)).into_response(),
-            None => (StatusCode::NOT_FOUND).into_response(),
-        },
+        Ok(Some(similar)) => (StatusCode::OK, Json(similar)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND).into_response(),
         Err(e) => {
-            tracing::warn!("could not get image exists: {}", e);
+            tracing::warn!("could not get similar images: {}", e);
+            error_response(&e, StatusCode::BAD_REQUEST, "could not get similar images")
+        }
+    };
+}
+
+async fn delete_image_route(
+    State(container): State<Arc<Container>>,
+    Path(file_name): Path<String>,
+) -> Response {
+    if !Variant::is_valid_file_name(&file_name) {
+        return invalid_file_name_response();
+    }
+
+    return match container.delete_image.execute(file_name.clone()).await {
+        Ok(deleted) if deleted.is_empty() => (StatusCode::NOT_FOUND).into_response(),
+        Ok(deleted) => (
+            StatusCode::OK,
+            Json(DeleteImageResponse { file_name, deleted }),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("could not delete image: {}", e);
             (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: String::from("could not delete image"),
+                }),
+            )
+                .into_response()
+        }
+    };
+}
+
+async fn transform_image_route(
+    State(container): State<Arc<Container>>,
+    Path(file_name): Path<String>,
+    Query(query): Query<TransformQuery>,
+) -> Response {
+    if !Variant::is_valid_file_name(&file_name) {
+        return invalid_file_name_response();
+    }
+
+    let profile = match get_transform_profile(query) {
+        Ok(profile) => profile,
+        Err(e) => {
+            return (
                 StatusCode::BAD_REQUEST,
                 Json(ErrorResponse {
-                    error: String::from("could not get image exists"),
+                    error: e.to_string(),
                 }),
             )
                 .into_response()
         }
     };
+
+    return match container.transform_image.execute(file_name, profile).await {
+        Ok(Some((body, content_type))) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, content_type),
+                (header::CACHE_CONTROL, String::from("max-age=31536000")),
+            ],
+            body,
+        )
+            .into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND).into_response(),
+        Err(e) => {
+            tracing::warn!("could not transform image: {}", e);
+            error_response(&e, StatusCode::BAD_REQUEST, "could not transform image")
+        }
+    };
+}
+
+// how busy the processing pools are, for sizing instances
+async fn stats_route(State(container): State<Arc<Container>>) -> Response {
+    (
+        StatusCode::OK,
+        Json(StatsResponse {
+            images: container.image_pool.stats(),
+            video: container.video_pool.stats(),
+        }),
+    )
+        .into_response()
+}
+
+// a missing dimension leaves that side unconstrained, which only makes sense when
+// preserving aspect ratio
+fn get_transform_profile(query: TransformQuery) -> Result<Profile> {
+    let fit = query.fit.unwrap_or_default();
+
+    let (width, height) = match (query.w, query.h, &fit) {
+        (Some(w), Some(h), _) => (w, h),
+        (Some(w), None, Fit::Contain) => (w, MAX_TRANSFORM_DIMENSION),
+        (None, Some(h), Fit::Contain) => (MAX_TRANSFORM_DIMENSION, h),
+        (None, None, _) => bail!("w or h is required"),
+        _ => bail!("w and h are required for {} fit", fit.name()),
+    };
+
+    if width > MAX_TRANSFORM_DIMENSION || height > MAX_TRANSFORM_DIMENSION {
+        bail!("w and h must be at most {}", MAX_TRANSFORM_DIMENSION);
+    }
+
+    let profile = profile::transform(
+        width,
+        height,
+        fit,
+        query.format.unwrap_or(Format::WebP),
+        query.q.unwrap_or(80),
+    );
+
+    profile.validate()?;
+
+    Ok(profile)
+}
+
+fn error_response(e: &anyhow::Error, default: StatusCode, message: &str) -> Response {
+    let body = Json(ErrorResponse {
+        error: String::from(message),
+    });
+
+    match error_status(e, default) {
+        StatusCode::SERVICE_UNAVAILABLE => {
+            (StatusCode::SERVICE_UNAVAILABLE, retry_after(), body).into_response()
+        }
+        status => (status, body).into_response(),
+    }
+}
+
+fn retry_after() -> [(HeaderName, String); 1] {
+    [(header::RETRY_AFTER, RETRY_AFTER_SECONDS.to_string())]
+}
+
+// typed errors from the gateways decide the status, anything else gets the route's default
+fn error_status(e: &anyhow::Error, default: StatusCode) -> StatusCode {
+    if errors::find::<Overloaded>(e).is_some() {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+    if errors::find::<ForbiddenUrl>(e).is_some() || errors::find::<InvalidMedia>(e).is_some() {
+        return StatusCode::UNPROCESSABLE_ENTITY;
+    }
+    if errors::find::<MediaTooLarge>(e).is_some() {
+        return StatusCode::PAYLOAD_TOO_LARGE;
+    }
+
+    default
+}
+
+// variants are addressed by their configured name, defaulting to thumbnails
+fn get_variant(container: &Container, query: VariantQuery) -> Option<Variant> {
+    let name = query
+        .variant
+        .unwrap_or_else(|| String::from(settings::THUMBNAIL));
+
+    container.settings.variant(&name)
+}
+
+fn invalid_file_name_response() -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse {
+            error: String::from("invalid file name"),
+        }),
+    )
+        .into_response()
+}
+
+fn unknown_variant_response() -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse {
+            error: String::from("unknown variant"),
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize, Debug)]
+struct VariantQuery {
+    variant: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SimilarQuery {
+    variant: Option<String>,
+    distance: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TransformQuery {
+    w: Option<u32>,
+    h: Option<u32>,
+    fit: Option<Fit>,
+    format: Option<Format>,
+    q: Option<u8>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -209,7 +654,207 @@ struct UploadAvatarRequest {
     is_nft: bool,
 }
 
+#[derive(Serialize, Debug)]
+struct DeleteImageResponse {
+    file_name: String,
+    deleted: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct StatsResponse {
+    images: Stats,
+    video: Stats,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum UploadResult {
+    Image(Box<Image>),
+    Error(UploadError),
+}
+
+#[derive(Serialize, Debug)]
+struct UploadError {
+    status: u16,
+    error: String,
+}
+
 #[derive(Serialize, Debug)]
 struct ErrorResponse {
     error: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{container, usecases::fakes::FakeStorage};
+
+    struct Setup {
+        storage: Arc<FakeStorage>,
+        app: Router,
+    }
+
+    fn setup(settings: settings::Settings) -> Setup {
+        let index = std::env::temp_dir().join(format!("images-http-{}.jsonl", Uuid::new_v4()));
+        let settings = settings.with_index_path(index.to_string_lossy().to_string());
+        let storage = FakeStorage::new();
+        let container = container::with_storage(Arc::new(settings), storage.clone()).unwrap();
+
+        Setup {
+            storage,
+            app: router(Arc::new(container)),
+        }
+    }
+
+    fn request(method: &str, uri: &str) -> axum::http::request::Builder {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, "Bearer api-key")
+    }
+
+    fn png() -> Vec<u8> {
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::new_rgb8(4, 4)
+            .write_to(&mut buffer, image::ImageOutputFormat::Png)
+            .unwrap();
+        buffer.into_inner()
+    }
+
+    // a form with one file part per image, the content type is in odd case on purpose
+    fn form(files: &[&[u8]]) -> Request<Body> {
+        let mut body = Vec::new();
+        for (i, data) in files.iter().enumerate() {
+            body.extend_from_slice(
+                format!(
+                    "--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n",
+                    i
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--boundary--\r\n");
+
+        request("POST", "/v1/images")
+            .header(
+                header::CONTENT_TYPE,
+                "Multipart/Form-Data; boundary=boundary",
+            )
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn json(response: Response) -> serde_json::Value {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn creates_every_file_of_a_form_upload() {
+        let setup = setup(settings::test());
+
+        let response = setup
+            .app
+            .clone()
+            .oneshot(form(&[&png(), &png()]))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let results = json(response).await;
+        assert_eq!(results.as_array().unwrap().len(), 2);
+        assert_ne!(results[0]["file_name"], results[1]["file_name"]);
+    }
+
+    #[tokio::test]
+    async fn reports_failed_files_as_a_single_upload_would() {
+        let setup = setup(settings::test());
+        let single = setup
+            .app
+            .clone()
+            .oneshot(
+                request("POST", "/v1/images")
+                    .body(Body::from("not an image"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = single.status();
+        let error = json(single).await;
+
+        let response = setup
+            .app
+            .clone()
+            .oneshot(form(&[&png(), b"not an image"]))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let results = json(response).await;
+        assert!(results[0]["file_name"].is_string());
+        assert_eq!(results[1]["status"], status.as_u16());
+        assert_eq!(results[1]["error"], error["error"]);
+    }
+
+    #[tokio::test]
+    async fn answers_duplicate_uploads_with_the_existing_image() {
+        let setup = setup(settings::test().with_content_addressed(true));
+        let upload = || {
+            request("POST", "/v1/images")
+                .body(Body::from(png()))
+                .unwrap()
+        };
+
+        let response = setup.app.clone().oneshot(upload()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let uploads = setup.storage.uploads().len();
+
+        let response = setup.app.clone().oneshot(upload()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(setup.storage.uploads().len(), uploads);
+    }
+
+    #[tokio::test]
+    async fn rejects_file_names_that_leave_their_directory() {
+        let setup = setup(settings::test());
+        setup
+            .storage
+            .insert(Variant::Original, "a", "image/png", b"original");
+
+        for uri in [
+            "/v1/images/..%2Foriginals",
+            "/v1/images/..%2F..",
+            "/v1/images/a%5C..",
+            "/v1/images/a%00",
+        ] {
+            let response = setup
+                .app
+                .clone()
+                .oneshot(request("DELETE", uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+        }
+
+        let response = setup
+            .app
+            .clone()
+            .oneshot(
+                request("GET", "/v1/images/..%2Foriginals/similar")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        assert!(setup.storage.deletes().is_empty());
+        assert!(setup.storage.object(Variant::Original, "a").is_some());
+    }
+}
@@ -1,16 +1,49 @@
 use serde::Serialize;
 
-#[derive(Debug, Clone, Serialize)]
+use crate::common::attributes::Attributes;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Image {
     file_name: String,
     original: Header,
     formatted: Header,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    poster: Option<Header>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blurhash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dominant_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    palette: Option<Vec<String>>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+// layout details are left out for objects stored before they were recorded
+#[derive(Debug, Clone, PartialEq, Serialize)]
 struct Header {
     url: String,
     content_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frame_rate: Option<f64>,
+}
+
+fn header(url: String, content_type: String, attributes: &Attributes) -> Header {
+    Header {
+        url,
+        content_type,
+        width: attributes.width(),
+        height: attributes.height(),
+        size: attributes.size(),
+        duration: attributes.duration(),
+        frame_rate: attributes.frame_rate(),
+    }
 }
 
 pub fn new(
@@ -20,15 +53,41 @@ pub fn new(
     formatted_url: String,
     formatted_content_type: String,
 ) -> Image {
+    let attributes = Attributes::default();
+
     Image {
         file_name,
-        original: Header {
-            url: original_url,
-            content_type: original_content_type,
-        },
-        formatted: Header {
-            url: formatted_url,
-            content_type: formatted_content_type,
-        },
+        original: header(original_url, original_content_type, &attributes),
+        formatted: header(formatted_url, formatted_content_type, &attributes),
+        poster: None,
+        blurhash: None,
+        dominant_color: None,
+        palette: None,
+    }
+}
+
+impl Image {
+    pub fn with_poster(
+        mut self,
+        url: String,
+        content_type: String,
+        attributes: &Attributes,
+    ) -> Image {
+        self.poster = Some(header(url, content_type, attributes));
+        self
+    }
+
+    // the placeholder and colors belong to the formatted object, it is what the client
+    // waits for
+    pub fn with_attributes(mut self, original: &Attributes, formatted: &Attributes) -> Image {
+        self.original = header(self.original.url, self.original.content_type, original);
+        self.formatted = header(self.formatted.url, self.formatted.content_type, formatted);
+        self.blurhash = formatted.blurhash();
+        self.palette = formatted.palette();
+        self.dominant_color = self
+            .palette
+            .as_ref()
+            .and_then(|palette| palette.first().cloned());
+        self
     }
 }
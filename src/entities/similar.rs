@@ -0,0 +1,15 @@
+use serde::Serialize;
+
+use super::image::Image;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Similar {
+    #[serde(flatten)]
+    image: Image,
+    // hamming distance between the fingerprints, 0 for visually identical images
+    distance: u32,
+}
+
+pub fn new(image: Image, distance: u32) -> Similar {
+    Similar { image, distance }
+}
@@ -0,0 +1,328 @@
+use std::io::Cursor;
+
+use anyhow::{Context, Result};
+use image::{
+    codecs::{
+        png::{CompressionType, FilterType, PngEncoder},
+        webp::WebPDecoder,
+    },
+    imageops, AnimationDecoder, ImageDecoder, ImageEncoder, RgbaImage,
+};
+use img_parts::{
+    webp::{WebP, CHUNK_ANMF, CHUNK_VP8X},
+    Bytes,
+};
+
+use crate::common::{
+    attributes::{self, Attributes},
+    errors::{invalid_media, media_too_large},
+};
+
+use super::video::{MAX_INPUT_DIMENSION, MAX_INPUT_FRAMES};
+
+// the decoder holds every frame in memory at once, bounded before it runs
+const MAX_DECODED_BYTES: u64 = 256 * 1024 * 1024;
+// encoded frames, each distinct one is kept once however often it is shown
+const MAX_SEQUENCE_BYTES: usize = 64 * 1024 * 1024;
+
+// like browsers, frames without a usable delay are shown for 100ms
+const MIN_DELAY_MS: f64 = 10.0;
+const DEFAULT_DELAY_MS: f64 = 100.0;
+
+// A png per frame and the order they are shown in at a constant rate, so a frame
+// that is repeated for a long delay is still only held once.
+#[derive(Debug)]
+pub struct Sequence {
+    frames: Vec<Vec<u8>>,
+    samples: Vec<usize>,
+}
+
+impl Sequence {
+    // every sample in order, repeats share the same bytes
+    pub fn samples(&self) -> Vec<&[u8]> {
+        self.samples
+            .iter()
+            .map(|frame| self.frames[*frame].as_slice())
+            .collect()
+    }
+}
+
+// Decodes an animated webp into a png sequence at a constant frame rate, ffmpeg's
+// webp demuxer only reads still images. Frames are repeated or dropped to match
+// the rate and the sequence stops after the given number of seconds. Frames are
+// scaled down to cover the given size, ffmpeg fits them exactly later on.
+pub fn png_sequence(data: &[u8], fps: u32, seconds: u32, size: (u32, u32)) -> Result<Sequence> {
+    check(data)?;
+
+    let decoder = WebPDecoder::new(Cursor::new(data)).context("could not decode webp")?;
+    let (width, height) = cover(decoder.dimensions(), size);
+
+    let interval = 1000.0 / fps as f64;
+    let max_samples = (fps as u64 * seconds as u64).min(MAX_INPUT_FRAMES);
+
+    let mut sequence = Sequence {
+        frames: Vec::new(),
+        samples: Vec::new(),
+    };
+    let mut bytes = 0;
+    let mut end = 0.0;
+
+    for frame in decoder.into_frames() {
+        let frame = frame.context("could not decode frame")?;
+
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        end += delay(numerator as f64 / denominator as f64);
+
+        // every sample that falls within this frame's display time shows it
+        let mut encoded = None;
+        while (sequence.samples.len() as u64) < max_samples
+            && (sequence.samples.len() as f64) * interval < end
+        {
+            let index = match encoded {
+                Some(index) => index,
+                None => {
+                    let png = encode(frame.buffer(), width, height)?;
+                    bytes += png.len();
+                    if bytes > MAX_SEQUENCE_BYTES {
+                        return Err(
+                            media_too_large(format!("over {} bytes of frames", bytes)).into()
+                        );
+                    }
+
+                    sequence.frames.push(png);
+                    *encoded.insert(sequence.frames.len() - 1)
+                }
+            };
+            sequence.samples.push(index);
+        }
+
+        if sequence.samples.len() as u64 >= max_samples {
+            break;
+        }
+    }
+
+    if sequence.samples.is_empty() {
+        return Err(invalid_media(String::from("no frames")).into());
+    }
+
+    Ok(sequence)
+}
+
+// the smallest size that still covers the box, never larger than the original
+fn cover((width, height): (u32, u32), (box_width, box_height): (u32, u32)) -> (u32, u32) {
+    let ratio = (box_width as f64 / width as f64).max(box_height as f64 / height as f64);
+
+    match ratio < 1.0 {
+        true => (
+            ((width as f64 * ratio).ceil() as u32).max(1),
+            ((height as f64 * ratio).ceil() as u32).max(1),
+        ),
+        false => (width, height),
+    }
+}
+
+// canvas size, duration and average frame rate, read from the headers alone
+pub fn describe(data: &[u8]) -> Result<Attributes> {
+    let header = header(data)?;
+    let duration = header.duration_ms / 1000.0;
+
+    Ok(attributes::new()
+        .with_dimensions(header.width, header.height)
+        .with_duration(duration)
+        .with_frame_rate(header.frames as f64 / duration))
+}
+
+// reads the frame headers, which are cheap, before any frame is decoded
+fn check(data: &[u8]) -> Result<()> {
+    let header = header(data)?;
+
+    if header.width > MAX_INPUT_DIMENSION || header.height > MAX_INPUT_DIMENSION {
+        return Err(
+            media_too_large(format!("dimensions {}x{}", header.width, header.height)).into(),
+        );
+    }
+
+    if header.frames > MAX_INPUT_FRAMES {
+        return Err(media_too_large(format!("{} frames", header.frames)).into());
+    }
+
+    if header.bytes > MAX_DECODED_BYTES {
+        return Err(media_too_large(format!("{} bytes of frames", header.bytes)).into());
+    }
+
+    Ok(())
+}
+
+struct Header {
+    width: u32,
+    height: u32,
+    frames: u64,
+    // decoded size of all frames
+    bytes: u64,
+    duration_ms: f64,
+}
+
+fn header(data: &[u8]) -> Result<Header> {
+    let webp = WebP::from_bytes(Bytes::copy_from_slice(data))
+        .map_err(|e| invalid_media(format!("could not parse webp: {}", e)))?;
+
+    // flags and reserved bytes, then the canvas width and height less one, img-parts
+    // reads them from the wrong offset
+    let (width, height) = match webp
+        .chunk_by_id(CHUNK_VP8X)
+        .and_then(|chunk| chunk.content().data())
+    {
+        Some(header) if header.len() >= 10 => (u24(&header[4..7]) + 1, u24(&header[7..10]) + 1),
+        _ => return Err(invalid_media(String::from("webp is not animated")).into()),
+    };
+
+    let mut header = Header {
+        width,
+        height,
+        frames: 0,
+        bytes: 0,
+        duration_ms: 0.0,
+    };
+
+    for chunk in webp.chunks_by_id(CHUNK_ANMF) {
+        // x and y offsets, then width and height less one and the duration in ms, all
+        // 24 bit little endian
+        let (frame_width, frame_height, duration) = match chunk.content().data() {
+            Some(frame) if frame.len() >= 15 => (
+                u24(&frame[6..9]) + 1,
+                u24(&frame[9..12]) + 1,
+                u24(&frame[12..15]),
+            ),
+            _ => return Err(invalid_media(String::from("invalid frame header")).into()),
+        };
+
+        header.frames += 1;
+        header.bytes += frame_width as u64 * frame_height as u64 * 4;
+        header.duration_ms += delay(duration as f64);
+    }
+
+    if header.frames == 0 {
+        return Err(invalid_media(String::from("no frames")).into());
+    }
+
+    Ok(header)
+}
+
+fn delay(ms: f64) -> f64 {
+    match ms {
+        ms if ms < MIN_DELAY_MS => DEFAULT_DELAY_MS,
+        ms => ms,
+    }
+}
+
+fn u24(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0])
+}
+
+// fast over small, ffmpeg decodes the sequence straight away
+fn encode(image: &RgbaImage, width: u32, height: u32) -> Result<Vec<u8>> {
+    let resized;
+    let image = match image.dimensions() == (width, height) {
+        true => image,
+        false => {
+            resized = imageops::resize(image, width, height, imageops::FilterType::Triangle);
+            &resized
+        }
+    };
+
+    let mut buffer = Vec::new();
+    PngEncoder::new_with_quality(&mut buffer, CompressionType::Fast, FilterType::NoFilter)
+        .write_image(image, width, height, image::ColorType::Rgba8)
+        .context("could not encode frame")?;
+
+    Ok(buffer)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use webp::{AnimEncoder, AnimFrame, WebPConfig};
+
+    use super::*;
+    use crate::common::errors::{self, MediaTooLarge};
+
+    // a 4x4 animation of a red frame for 500ms and then a blue one
+    pub fn animated_webp() -> Vec<u8> {
+        let red = RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255]));
+        let blue = RgbaImage::from_pixel(4, 4, image::Rgba([0, 0, 255, 255]));
+        let mut config = WebPConfig::new().unwrap();
+        config.lossless = 1;
+
+        let mut encoder = AnimEncoder::new(4, 4, &config);
+        encoder.add_frame(AnimFrame::from_rgba(&red, 4, 4, 0));
+        encoder.add_frame(AnimFrame::from_rgba(&blue, 4, 4, 500));
+
+        encoder.encode().to_vec()
+    }
+
+    fn frames(sequence: &Sequence) -> Vec<[u8; 4]> {
+        sequence
+            .samples()
+            .iter()
+            .map(|png| {
+                image::load_from_memory(png)
+                    .unwrap()
+                    .to_rgba8()
+                    .get_pixel(0, 0)
+                    .0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resamples_frames_to_a_constant_rate() {
+        let sequence = png_sequence(&animated_webp(), 4, 10, (4, 4)).unwrap();
+        let frames = frames(&sequence);
+
+        // each frame is encoded once however often it is shown
+        assert_eq!(sequence.frames.len(), 2);
+        // red covers the samples at 0 and 250ms, blue starts at 500ms
+        assert!(frames.len() >= 3, "{:?}", frames);
+        assert_eq!(frames[0], [255, 0, 0, 255]);
+        assert_eq!(frames[1], [255, 0, 0, 255]);
+        assert_eq!(frames[2], [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn stops_after_max_duration() {
+        let frames = frames(&png_sequence(&animated_webp(), 2, 1, (4, 4)).unwrap());
+
+        assert_eq!(frames.len(), 2);
+    }
+
+    #[test]
+    fn scales_frames_down_to_cover_the_box() {
+        let sequence = png_sequence(&animated_webp(), 4, 10, (2, 1)).unwrap();
+
+        let frame = image::load_from_memory(sequence.samples()[0]).unwrap();
+        assert_eq!((frame.width(), frame.height()), (2, 2));
+        assert_eq!(cover((4096, 4096), (300, 200)), (300, 300));
+        assert_eq!(cover((100, 50), (300, 200)), (100, 50));
+    }
+
+    #[test]
+    fn describes_from_headers() {
+        let attributes = describe(&animated_webp()).unwrap();
+
+        assert_eq!(
+            (attributes.width(), attributes.height()),
+            (Some(4), Some(4))
+        );
+        assert!(attributes.duration().unwrap() > 0.5);
+        assert!(attributes.frame_rate().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn rejects_oversized_canvas() {
+        let mut data = animated_webp();
+        // canvas width less one in the vp8x chunk
+        data[24..27].copy_from_slice(&[0xff, 0xff, 0]);
+
+        let e = png_sequence(&data, 4, 10, (4, 4)).unwrap_err();
+        assert!(errors::find::<MediaTooLarge>(&e).is_some());
+    }
+}
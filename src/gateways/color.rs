@@ -0,0 +1,160 @@
+use anyhow::{bail, Result};
+use image::DynamicImage;
+use img_parts::{Bytes, DynImage, ImageICC};
+use qcms::{DataType, Intent, Profile, Transform};
+
+// offset and value of the data colour space in an icc header
+const COLOR_SPACE: std::ops::Range<usize> = 16..20;
+const RGB: &[u8] = b"RGB ";
+
+// Converts pixels out of their embedded color profile. Outputs carry no profile,
+// so anything other than srgb pixels is displayed washed out or oversaturated.
+pub struct Color {
+    srgb: Box<Profile>,
+}
+
+pub fn new() -> Color {
+    let mut srgb = Profile::new_sRGB();
+    srgb.precache_output_transform();
+
+    Color { srgb }
+}
+
+impl Color {
+    pub fn to_srgb(&self, image: &DynamicImage, icc: &[u8]) -> Result<DynamicImage> {
+        // cmyk and gray profiles describe data the decoder has already turned into rgb
+        if icc.get(COLOR_SPACE) != Some(RGB) {
+            bail!("unsupported profile color space");
+        }
+
+        let profile = match Profile::new_from_slice(icc, false) {
+            Some(profile) => profile,
+            None => bail!("could not parse profile"),
+        };
+
+        let data_type = match image.color().has_alpha() {
+            true => DataType::RGBA8,
+            false => DataType::RGB8,
+        };
+
+        let transform = match Transform::new(&profile, &self.srgb, data_type, Intent::Perceptual) {
+            Some(transform) => transform,
+            None => bail!("could not create transform"),
+        };
+
+        Ok(match data_type {
+            DataType::RGBA8 => {
+                let mut pixels = image.to_rgba8();
+                transform.apply(&mut pixels);
+                DynamicImage::ImageRgba8(pixels)
+            }
+            _ => {
+                let mut pixels = image.to_rgb8();
+                transform.apply(&mut pixels);
+                DynamicImage::ImageRgb8(pixels)
+            }
+        })
+    }
+}
+
+// the raw embedded profile of a jpeg, png or webp
+pub fn icc_profile(data: &[u8]) -> Option<Vec<u8>> {
+    DynImage::from_bytes(Bytes::copy_from_slice(data))
+        .ok()
+        .flatten()
+        .and_then(|image| image.icc_profile())
+        .map(|icc| icc.to_vec())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    fn s15_fixed16(value: f64) -> [u8; 4] {
+        ((value * 65536.0).round() as i32).to_be_bytes()
+    }
+
+    fn xyz(x: f64, y: f64, z: f64) -> Vec<u8> {
+        [
+            b"XYZ ".as_slice(),
+            &[0; 4],
+            &s15_fixed16(x),
+            &s15_fixed16(y),
+            &s15_fixed16(z),
+        ]
+        .concat()
+    }
+
+    // a minimal v2 matrix/trc profile for display p3, primaries adapted to d50
+    pub fn display_p3_icc() -> Vec<u8> {
+        let trc = [
+            b"curv".as_slice(),
+            &[0; 4],
+            &1u32.to_be_bytes(),
+            &[2, 0x33],
+            &[0; 2],
+        ]
+        .concat();
+        let tags: [(&[u8; 4], Vec<u8>); 7] = [
+            (b"wtpt", xyz(0.9642, 1.0, 0.8249)),
+            (b"rXYZ", xyz(0.5151, 0.2412, -0.0011)),
+            (b"gXYZ", xyz(0.2919, 0.6922, 0.0419)),
+            (b"bXYZ", xyz(0.1571, 0.0666, 0.7841)),
+            (b"rTRC", trc.clone()),
+            (b"gTRC", trc.clone()),
+            (b"bTRC", trc),
+        ];
+
+        let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+        let mut data = Vec::new();
+        let mut offset = 128 + 4 + 12 * tags.len();
+        for (signature, tag) in &tags {
+            table.extend_from_slice(*signature);
+            table.extend_from_slice(&(offset as u32).to_be_bytes());
+            table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+            data.extend_from_slice(tag);
+            offset += tag.len();
+        }
+
+        let size = (128 + table.len() + data.len()) as u32;
+        let header = [
+            size.to_be_bytes().as_slice(),
+            &[0; 4],
+            &[2, 0x10, 0, 0],
+            b"mntr",
+            b"RGB ",
+            b"XYZ ",
+            &[0; 12],
+            b"acsp",
+            &[0; 24],
+            &[0; 4],
+            &xyz(0.9642, 1.0, 0.8249)[8..],
+            &[0; 48],
+        ]
+        .concat();
+
+        [header, table, data].concat()
+    }
+
+    #[test]
+    fn converts_display_p3_to_srgb() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, Rgb([200, 80, 60])));
+
+        let converted = new().to_srgb(&image, &display_p3_icc()).unwrap();
+
+        // the same numbers mean a more saturated red in p3, so srgb needs more red
+        let [red, green, _] = converted.to_rgb8().get_pixel(0, 0).0;
+        assert!(red > 210, "{}", red);
+        assert!(green < 80, "{}", green);
+    }
+
+    #[test]
+    fn rejects_non_rgb_profiles() {
+        let mut icc = display_p3_icc();
+        icc[COLOR_SPACE].copy_from_slice(b"CMYK");
+
+        assert!(new().to_srgb(&DynamicImage::new_rgb8(1, 1), &icc).is_err());
+    }
+}
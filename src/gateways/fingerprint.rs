@@ -0,0 +1,54 @@
+use image::{imageops::FilterType, DynamicImage};
+
+// one more column than bits per row since each bit compares neighbours
+const WIDTH: u32 = 9;
+const HEIGHT: u32 = 8;
+
+// Difference hash: the image is shrunk to 9x8 grayscale and each bit records whether
+// a pixel is brighter than its right neighbour. Re-encoding, resizing and small color
+// shifts keep the gradients, so copies land within a few bits of each other.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let image = image
+        .resize_exact(WIDTH, HEIGHT, FilterType::Triangle)
+        .to_luma8();
+
+    let mut hash = 0;
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH - 1 {
+            let brighter = image.get_pixel(x, y)[0] > image.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let value = ((x * 7 + y * 3) % 256) as u8;
+            Rgb([value, 255 - value, value / 2])
+        }))
+    }
+
+    #[test]
+    fn resized_copies_hash_alike() {
+        let original = dhash(&gradient(200, 100));
+        let resized = dhash(&gradient(200, 100).resize_exact(90, 45, FilterType::Nearest));
+
+        assert!((original ^ resized).count_ones() <= 4);
+    }
+
+    #[test]
+    fn different_images_hash_apart() {
+        let original = dhash(&gradient(200, 100));
+        let flipped = dhash(&gradient(200, 100).fliph());
+
+        assert!((original ^ flipped).count_ones() > 16);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{
+    common::{attributes::Attributes, variant::Variant},
+    settings::Settings,
+    usecases::gateways::Storage,
+};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+use tokio::fs;
+
+struct Fs {
+    settings: Arc<Settings>,
+    root: PathBuf,
+}
+
+pub fn new(settings: Arc<Settings>) -> impl Storage {
+    let root = PathBuf::from(settings.storage_root());
+
+    Fs { settings, root }
+}
+
+// stored next to each object since the filesystem has nowhere else to keep it
+#[derive(Debug, Serialize, Deserialize)]
+struct Sidecar {
+    content_type: String,
+    #[serde(default)]
+    attributes: Attributes,
+}
+
+#[async_trait]
+impl Storage for Fs {
+    async fn upload(
+        &self,
+        file_name: String,
+        variant: Variant,
+        content_type: String,
+        body: Vec<u8>,
+        attributes: Attributes,
+    ) -> Result<String> {
+        let key = self.get_key(file_name, variant);
+        let path = self.get_path(&key)?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .context("could not create image directory")?;
+        }
+
+        let sidecar = serde_json::to_vec(&Sidecar {
+            content_type,
+            attributes,
+        })
+        .context("could not serialize image sidecar")?;
+
+        fs::write(&path, body)
+            .await
+            .context("could not write image")?;
+        fs::write(self.get_sidecar_path(&key)?, sidecar)
+            .await
+            .context("could not write image sidecar")?;
+
+        Ok(self.get_external_url(key))
+    }
+
+    async fn get(
+        &self,
+        variant: Variant,
+        file_name: String,
+    ) -> Result<Option<(String, String, Attributes)>> {
+        let key = self.get_key(file_name, variant);
+
+        if !fs::try_exists(self.get_path(&key)?)
+            .await
+            .context("could not check if image exists")?
+        {
+            return Ok(None);
+        }
+
+        let sidecar = match fs::read(self.get_sidecar_path(&key)?).await {
+            Ok(sidecar) => sidecar,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("could not read image sidecar"),
+        };
+
+        let sidecar = serde_json::from_slice::<Sidecar>(&sidecar)
+            .with_context(|| format!("could not deserialize sidecar for {}", key))?;
+        let url = self.get_external_url(key);
+
+        Ok(Some((url, sidecar.content_type, sidecar.attributes)))
+    }
+
+    async fn delete(&self, variant: Variant, file_name: String) -> Result<Option<String>> {
+        let key = self.get_key(file_name, variant);
+
+        match fs::remove_file(self.get_path(&key)?).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("could not delete image"),
+        };
+
+        match fs::remove_file(self.get_sidecar_path(&key)?).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context("could not delete image sidecar"),
+        };
+
+        Ok(Some(key))
+    }
+
+    async fn download(
+        &self,
+        variant: Variant,
+        file_name: String,
+    ) -> Result<Option<(Vec<u8>, String)>> {
+        let key = self.get_key(file_name.clone(), variant.clone());
+
+        let content_type = match self.get(variant, file_name).await? {
+            Some((_, content_type, _)) => content_type,
+            None => return Ok(None),
+        };
+
+        match fs::read(self.get_path(&key)?).await {
+            Ok(body) => Ok(Some((body, content_type))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context("could not read image"),
+        }
+    }
+
+    async fn delete_transforms(&self, file_name: String) -> Result<Vec<String>> {
+        let prefix = Variant::transforms_prefix(&file_name);
+
+        let mut entries = match fs::read_dir(self.get_path(&prefix)?).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("could not read transforms directory"),
+        };
+
+        let mut deleted = Vec::new();
+
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context("could not read transforms directory entry")?
+        {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            fs::remove_file(entry.path())
+                .await
+                .context("could not delete transform")?;
+
+            // sidecars are an implementation detail, only report the objects
+            if !name.ends_with(".json") {
+                deleted.push(format!("{}/{}", prefix, name));
+            }
+        }
+
+        fs::remove_dir(self.get_path(&prefix)?)
+            .await
+            .context("could not delete transforms directory")?;
+
+        Ok(deleted)
+    }
+}
+
+impl Fs {
+    fn get_key(&self, id: String, variant: Variant) -> String {
+        variant.key(&id)
+    }
+
+    // keys are built from file names out of the request path, a `..` or absolute
+    // component would reach outside the root
+    fn get_path(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!("invalid storage key {}", key);
+        }
+
+        Ok(self.root.join(relative))
+    }
+
+    fn get_sidecar_path(&self, key: &str) -> Result<PathBuf> {
+        self.get_path(&format!("{}.json", key))
+    }
+
+    fn get_external_url(&self, key: String) -> String {
+        format!("{}/{}", self.settings.storage_external_url(), key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings;
+
+    #[tokio::test]
+    async fn refuses_keys_outside_the_root() {
+        let storage = new(Arc::new(settings::test()));
+
+        assert!(storage
+            .delete_transforms(String::from("../originals"))
+            .await
+            .is_err());
+        assert!(storage
+            .get(Variant::Original, String::from("../../../etc/passwd"))
+            .await
+            .is_err());
+    }
+}
@@ -1,33 +1,141 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
-use crate::{settings::Settings, usecases::gateways::Web};
+use crate::{
+    common::errors::{self, forbidden_url, ForbiddenUrl},
+    settings::Settings,
+    usecases::gateways::Web,
+};
 use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine};
+use hyper::client::connect::dns::Name;
 use reqwest::{
+    dns::{Resolve, Resolving},
     redirect::{self},
-    Response,
+    Response, Url,
 };
 use serde::Deserialize;
 
 const MAX_BODY_SIZE: usize = 3 * 1024 * 1024;
 const MAX_REQUEST_DURATION_SECONDS: u64 = 30;
+const ALLOWED_SCHEMES: [&str; 4] = ["http", "https", "ipfs", "ipns"];
 
 struct Http {
     settings: Arc<Settings>,
+    // for urls supplied by users, refuses anything that isn't publicly routable
     client: reqwest::Client,
+    // for urls rewritten from ipfs:// and ipns://, the gateway may be on a private network
+    gateway_client: reqwest::Client,
 }
 
 pub fn new(settings: Arc<Settings>) -> impl Web {
+    build(settings)
+}
+
+fn build(settings: Arc<Settings>) -> Http {
     Http {
         settings,
-        client: reqwest::Client::builder()
-            .redirect(redirect::Policy::none())
-            .timeout(Duration::from_secs(MAX_REQUEST_DURATION_SECONDS))
+        client: client_builder()
+            .dns_resolver(Arc::new(GuardedResolver))
             .build()
             .unwrap(),
+        gateway_client: client_builder().build().unwrap(),
+    }
+}
+
+fn client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        // a proxy would resolve hosts itself and get around the guarded resolver
+        .no_proxy()
+        .redirect(redirect::Policy::none())
+        .timeout(Duration::from_secs(MAX_REQUEST_DURATION_SECONDS))
+}
+
+// Resolves hosts and refuses to hand back addresses that are not publicly routable.
+// Checking the resolved addresses that are actually connected to (rather than a
+// separate lookup up front) means a rebinding dns server can not swap them out.
+struct GuardedResolver;
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .collect::<Vec<SocketAddr>>();
+
+            if let Some(addr) = addrs.iter().find(|addr| !is_public(&addr.ip())) {
+                return Err(
+                    Box::new(forbidden_url(format!("{} resolves to {}", host, addr.ip()))) as _,
+                );
+            }
+
+            Ok(Box::new(addrs.into_iter()) as _)
+        })
+    }
+}
+
+// the rest of the url if it has the scheme, in any case
+fn strip_scheme<'a>(url: &'a str, scheme: &str) -> Option<&'a str> {
+    let prefix = format!("{}://", scheme);
+
+    match url.get(..prefix.len()) {
+        Some(head) if head.eq_ignore_ascii_case(&prefix) => Some(&url[prefix.len()..]),
+        _ => None,
+    }
+}
+
+fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => {
+            // ipv4 mapped ::ffff:a.b.c.d and ipv4 compatible ::a.b.c.d
+            if let Some(ip) = ip.to_ipv4() {
+                return is_public_v4(&ip);
+            }
+
+            let segments = ip.segments();
+
+            // 6to4 2002:aabb:ccdd::/48 tunnels to a.b.c.d
+            if segments[0] == 0x2002 {
+                let [a, b] = segments[1].to_be_bytes();
+                let [c, d] = segments[2].to_be_bytes();
+                return is_public_v4(&Ipv4Addr::new(a, b, c, d));
+            }
+
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || (segments[0] & 0xfe00) == 0xfc00 // unique local, includes aws fd00:ec2::254
+                || (segments[0] & 0xffc0) == 0xfe80 // link local
+                || (segments[0] == 0x64 && segments[1] == 0xff9b) // nat64
+                || (segments[0] == 0x2001 && segments[1] == 0xdb8)) // documentation
+        }
     }
 }
 
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+    let octets = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local() // includes the 169.254.169.254 metadata service
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || octets[0] == 0 // this network
+        || (octets[0] == 100 && (octets[1] & 0xc0) == 64) // carrier grade nat, includes alibaba metadata
+        || (octets[0] == 192 && octets[1] == 0 && octets[2] == 0) // ietf protocol assignments
+        || (octets[0] == 198 && (octets[1] & 0xfe) == 18) // benchmarking
+        || octets[0] >= 240) // reserved
+}
+
 #[derive(Debug, Deserialize)]
 struct NFTMetadata {
     image: Option<String>,
@@ -65,6 +173,11 @@ impl Web for Http {
     }
 
     async fn get_image_data(&self, url: String) -> Result<Vec<u8>> {
+        // on chain nfts embed the image itself rather than linking to it
+        if let Some(data) = self.read_inline_data(&url)? {
+            return Ok(data);
+        }
+
         tracing::info!("requesting image from {}", url);
 
         let response = self
@@ -83,14 +196,38 @@ impl Web for Http {
 
 impl Http {
     async fn get_with_status_check(&self, url: String) -> Result<Response> {
-        let url = self.parse_url(url);
+        let scheme = url
+            .split_once("://")
+            .map(|(scheme, _)| scheme.to_lowercase());
 
-        let resp = self
-            .client
-            .get(&url)
-            .send()
-            .await
-            .with_context(|| format!("could not get {}", url))?;
+        if !scheme.is_some_and(|scheme| ALLOWED_SCHEMES.contains(&scheme.as_str())) {
+            return Err(forbidden_url(format!("unsupported scheme in {}", url)).into());
+        }
+
+        let (url, client) = match self.parse_url(&url) {
+            Some(url) => {
+                self.check_gateway_origin(&url)?;
+                (url, &self.gateway_client)
+            }
+            None => {
+                self.check_literal_host(&url)?;
+                (url, &self.client)
+            }
+        };
+
+        let resp = match client.get(&url).send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                let e = anyhow::Error::new(e);
+
+                // surface the resolver's rejection rather than a generic connect error
+                if let Some(forbidden) = errors::find::<ForbiddenUrl>(&e) {
+                    return Err(forbidden_url(forbidden.to_string()).into());
+                }
+
+                return Err(e.context(format!("could not get {}", url)));
+            }
+        };
 
         if !resp.status().is_success() {
             bail!("invalid status for get {}: {}", url, resp.status());
@@ -113,27 +250,273 @@ impl Http {
         Ok(buf)
     }
 
-    fn parse_url(&self, url: String) -> String {
-        if let Some(suffix) = url.strip_prefix("ipfs://") {
-            let mut suffix = suffix.to_string();
+    // decodes data: uris and raw svg markup without making a request
+    fn read_inline_data(&self, url: &str) -> Result<Option<Vec<u8>>> {
+        let trimmed = url.trim_start();
+
+        let data = if let Some(uri) = trimmed.strip_prefix("data:") {
+            let (metadata, payload) = uri
+                .split_once(',')
+                .context("could not find data uri payload")?;
 
-            if !suffix.starts_with("ipfs/") {
-                suffix = format!("ipfs/{}", suffix);
+            if metadata.split(';').any(|param| param == "base64") {
+                // base64 payloads are often wrapped or percent encoded themselves
+                let payload = percent_encoding::percent_decode_str(payload)
+                    .decode_utf8()
+                    .context("could not percent decode data uri")?
+                    .split_whitespace()
+                    .collect::<String>();
+
+                // padding is frequently wrong or missing so ignore it entirely
+                general_purpose::STANDARD_NO_PAD
+                    .decode(payload.trim_end_matches('='))
+                    .context("could not base64 decode data uri")?
+            } else {
+                percent_encoding::percent_decode_str(payload).collect()
             }
+        } else if trimmed.starts_with("<svg") || trimmed.starts_with("<?xml") {
+            trimmed.as_bytes().to_vec()
+        } else {
+            return Ok(None);
+        };
 
-            return format!("{}/{}", self.settings.ipfs_gateway_url(), suffix);
+        if data.len() > MAX_BODY_SIZE {
+            bail!("inline image too large {}", data.len());
         }
 
-        if let Some(suffix) = url.strip_prefix("ipns://") {
-            let mut suffix = suffix.to_string();
+        Ok(Some(data))
+    }
 
-            if !suffix.starts_with("ipns/") {
-                suffix = format!("ipns/{}", suffix);
-            }
+    // ip literals never reach the resolver so have to be checked up front
+    fn check_literal_host(&self, url: &str) -> Result<()> {
+        let parsed = Url::parse(url).with_context(|| format!("could not parse {}", url))?;
+
+        let host = match parsed.host_str() {
+            Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+            None => return Err(forbidden_url(format!("missing host in {}", url)).into()),
+        };
+
+        let ip = match host.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => return Ok(()), // domain, checked by the resolver
+        };
 
-            return format!("{}/{}", self.settings.ipfs_gateway_url(), suffix);
+        if !is_public(&ip) {
+            return Err(forbidden_url(format!("{} is not a public address", ip)).into());
         }
 
-        url
+        Ok(())
+    }
+
+    // a rewritten url has to stay on the gateway, e.g. no userinfo tricks moving the host
+    fn check_gateway_origin(&self, url: &str) -> Result<()> {
+        let gateway = Url::parse(&self.settings.ipfs_gateway_url())
+            .context("could not parse ipfs gateway url")?;
+        let parsed = Url::parse(url).with_context(|| format!("could not parse {}", url))?;
+
+        if parsed.origin() != gateway.origin() {
+            return Err(forbidden_url(format!("{} is not on the ipfs gateway", url)).into());
+        }
+
+        Ok(())
+    }
+
+    // the gateway url for ipfs:// and ipns:// urls, none for anything else
+    fn parse_url(&self, url: &str) -> Option<String> {
+        let (namespace, suffix) = ["ipfs", "ipns"]
+            .into_iter()
+            .find_map(|scheme| strip_scheme(url, scheme).map(|suffix| (scheme, suffix)))?;
+
+        let mut suffix = suffix.to_string();
+
+        if !suffix.starts_with(&format!("{}/", namespace)) {
+            suffix = format!("{}/{}", namespace, suffix);
+        }
+
+        Some(format!("{}/{}", self.settings.ipfs_gateway_url(), suffix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings;
+
+    fn is_forbidden(result: Result<Response>) -> bool {
+        errors::find::<ForbiddenUrl>(&result.unwrap_err()).is_some()
+    }
+
+    #[test]
+    fn classifies_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.100.100.200",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00:ec2::254",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::127.0.0.1",
+            "::a9fe:a9fe",
+            "2002:7f00:1::",
+            "2002:a9fe:a9fe::1",
+        ] {
+            assert!(!is_public(&ip.parse().unwrap()), "{} should be blocked", ip);
+        }
+
+        for ip in [
+            "1.1.1.1",
+            "93.184.216.34",
+            "2606:4700:4700::1111",
+            "2002:101:101::1",
+        ] {
+            assert!(is_public(&ip.parse().unwrap()), "{} should be allowed", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn decodes_base64_data_uris() {
+        let http = build(Arc::new(settings::test()));
+
+        let data = http
+            .get_image_data(String::from(
+                "data:image/svg+xml;base64,PHN2ZyB4bWxucz0iaHR0cDovL3d3dy53My5vcmcvMjAwMC9zdmciLz4=",
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            data,
+            br#"<svg xmlns="http://www.w3.org/2000/svg"/>"#.to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn decodes_percent_encoded_data_uris() {
+        let http = build(Arc::new(settings::test()));
+
+        let data = http
+            .get_image_data(String::from(
+                "data:image/svg+xml;utf8,%3Csvg%20xmlns%3D%22http%3A%2F%2Fwww.w3.org%2F2000%2Fsvg%22%2F%3E",
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            data,
+            br#"<svg xmlns="http://www.w3.org/2000/svg"/>"#.to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn returns_raw_svg_markup() {
+        let http = build(Arc::new(settings::test()));
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg"><rect width="1" height="1"/></svg>"#;
+
+        let data = http.get_image_data(String::from(svg)).await.unwrap();
+
+        assert_eq!(data, svg.as_bytes().to_vec());
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_data_uris() {
+        let http = build(Arc::new(settings::test()));
+
+        assert!(http
+            .get_image_data(String::from("data:image/png;base64"))
+            .await
+            .is_err());
+        assert!(http
+            .get_image_data(String::from("data:image/png;base64,!!!"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_unsupported_schemes() {
+        let http = build(Arc::new(settings::test()));
+
+        assert!(is_forbidden(
+            http.get_with_status_check(String::from("file:///etc/passwd"))
+                .await
+        ));
+        assert!(is_forbidden(
+            http.get_with_status_check(String::from("gopher://example.com"))
+                .await
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_private_ip_literals() {
+        let http = build(Arc::new(settings::test()));
+
+        assert!(is_forbidden(
+            http.get_with_status_check(String::from("http://169.254.169.254/latest/meta-data"))
+                .await
+        ));
+        assert!(is_forbidden(
+            http.get_with_status_check(String::from("http://[::1]:8081/"))
+                .await
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_hosts_resolving_to_private_addresses() {
+        let http = build(Arc::new(settings::test()));
+
+        assert!(is_forbidden(
+            http.get_with_status_check(String::from("http://localhost:8081/"))
+                .await
+        ));
+    }
+
+    #[tokio::test]
+    async fn only_trusts_the_gateway_for_rewritten_urls() {
+        let settings = settings::test().with_ipfs_gateway_url(String::from("http://localhost:1"));
+        let http = build(Arc::new(settings));
+
+        // same host as the gateway, but supplied by the user rather than rewritten
+        assert!(is_forbidden(
+            http.get_with_status_check(String::from("http://localhost:5001/api/v0/config"))
+                .await
+        ));
+
+        // rewritten, so it reaches the gateway and only fails to connect
+        let result = http
+            .get_with_status_check(String::from("IPFS://Qm/1.png"))
+            .await;
+        assert!(!is_forbidden(result));
+    }
+
+    #[test]
+    fn rewrites_ipfs_urls_in_any_case() {
+        let http = build(Arc::new(settings::test()));
+
+        assert_eq!(
+            http.parse_url("IPFS://Qm/1.png"),
+            Some(String::from("https://ipfs.io/ipfs/Qm/1.png"))
+        );
+        assert_eq!(
+            http.parse_url("ipns://ipns/name"),
+            Some(String::from("https://ipfs.io/ipns/name"))
+        );
+        assert_eq!(http.parse_url("https://ipfs.io/ipfs/Qm"), None);
+    }
+
+    #[test]
+    fn keeps_rewritten_urls_on_the_gateway() {
+        let http = build(Arc::new(settings::test()));
+
+        assert!(http.check_gateway_origin("https://ipfs.io/ipfs/Qm").is_ok());
+        assert!(http
+            .check_gateway_origin("https://ipfs.io:5001/api/v0/config")
+            .is_err());
+        assert!(http.check_gateway_origin("http://ipfs.io/ipfs/Qm").is_err());
     }
 }
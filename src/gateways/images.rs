@@ -1,35 +1,229 @@
-use std::io::Cursor;
+use std::{io::Cursor, sync::Arc};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
+use image::{
+    codecs::{
+        jpeg::JpegEncoder,
+        webp::{WebPEncoder, WebPQuality},
+    },
+    imageops::FilterType,
+    io::{Limits, Reader},
+    ColorType, DynamicImage, ImageOutputFormat, RgbaImage,
+};
+use ravif::Img;
+use rgb::FromSlice;
 
 use crate::{
-    common::{format::Format, variant::Variant},
+    common::{
+        appearance::{self, Appearance},
+        attributes::{self, Attributes},
+        errors::media_too_large,
+        format::Format,
+        profile::{Fit, Profile},
+        variant::Variant,
+    },
+    settings::Settings,
     usecases::gateways::Images,
 };
 
-struct ImagesImpl {}
+use super::{
+    color::{self, Color},
+    fingerprint, metadata, palette,
+    pool::Pool,
+    svg::{self, Svg},
+};
+
+const PLACEHOLDER_SIZE: u32 = 32;
+// blurhash components along the longer and shorter side
+const PLACEHOLDER_COMPONENTS: (u32, u32) = (4, 3);
+const PALETTE_SIZE: u32 = 64;
+const PALETTE_COLORS: usize = 5;
+// shrunk with a fast filter before the exact resize the hash needs
+const FINGERPRINT_SIZE: u32 = 64;
+
+struct ImagesImpl {
+    pool: Arc<Pool>,
+    processor: Arc<Processor>,
+}
+
+// the cpu bound half of the gateway, only ever called from the blocking pool
+struct Processor {
+    settings: Arc<Settings>,
+    svg: Svg,
+    color: Color,
+}
 
-pub fn new() -> impl Images {
-    ImagesImpl {}
+pub fn new(settings: Arc<Settings>, pool: Arc<Pool>) -> impl Images {
+    ImagesImpl {
+        pool,
+        processor: Arc::new(Processor {
+            settings,
+            svg: svg::new(),
+            color: color::new(),
+        }),
+    }
 }
 
 #[async_trait]
 impl Images for ImagesImpl {
-    // TODO:
-    // - add avif support?
     async fn format(
         &self,
         data: &[u8],
         variant: Variant,
         input_format: Format,
     ) -> Result<(Vec<u8>, Format)> {
-        let (nwidth, nheight) = match variant {
-            Variant::Thumbnail => (300, 300),
-            Variant::Avatar => (125, 125),
-            Variant::Original => (800, 800),
+        let profile = match variant {
+            Variant::Profile(profile) | Variant::Transform(profile) => profile,
+            other => bail!("{} variant can not be formatted", other.name()),
         };
 
+        let processor = self.processor.clone();
+        let data = data.to_vec();
+        let output_format = profile.format();
+
+        let buffer = self
+            .pool
+            .run_blocking(move || processor.format(&data, &profile, input_format))
+            .await?;
+
+        Ok((buffer, output_format))
+    }
+
+    async fn appearance(&self, data: &[u8], input_format: Format) -> Result<Appearance> {
+        let processor = self.processor.clone();
+        let data = data.to_vec();
+
+        self.pool
+            .run_blocking(move || processor.appearance(&data, input_format))
+            .await
+    }
+
+    async fn describe(&self, data: &[u8], input_format: Format) -> Result<Attributes> {
+        let (width, height) = match input_format {
+            Format::Svg => {
+                let processor = self.processor.clone();
+                let data = data.to_vec();
+
+                self.pool
+                    .run_blocking(move || processor.svg.size(&data))
+                    .await?
+            }
+            _ => {
+                let size = imagesize::blob_size(data).context("could not read image size")?;
+                let (width, height) = (size.width as u32, size.height as u32);
+
+                // a quarter turn swaps the sides, see metadata::orient
+                match metadata::orientation(data) {
+                    5..=8 => (height, width),
+                    _ => (width, height),
+                }
+            }
+        };
+
+        Ok(attributes::new().with_dimensions(width, height))
+    }
+
+    async fn sanitize(&self, data: &[u8], input_format: Format) -> Result<Vec<u8>> {
+        match input_format {
+            Format::Svg => {
+                let processor = self.processor.clone();
+                let data = data.to_vec();
+
+                self.pool
+                    .run_blocking(move || {
+                        processor
+                            .svg
+                            .sanitize(&data)
+                            .context("could not sanitize svg")
+                    })
+                    .await
+            }
+            Format::Jpeg | Format::Png | Format::WebP
+                if self.processor.settings.strip_metadata() =>
+            {
+                let data = data.to_vec();
+
+                self.pool
+                    .run_blocking(move || {
+                        metadata::strip(&data, input_format).context("could not strip metadata")
+                    })
+                    .await
+            }
+            _ => Ok(data.to_vec()),
+        }
+    }
+}
+
+impl Processor {
+    fn format(&self, data: &[u8], profile: &Profile, input_format: Format) -> Result<Vec<u8>> {
+        let mut image = match input_format {
+            Format::Svg => self
+                .svg
+                .rasterize(data, profile.width(), profile.height())
+                .context("could not rasterize svg")?,
+            _ => self.load(data, input_format)?,
+        };
+
+        image = self.resize(image, profile);
+
+        self.encode(image, profile).context("could not write image")
+    }
+
+    fn appearance(&self, data: &[u8], input_format: Format) -> Result<Appearance> {
+        // decoded once at the largest size any of them needs, then scaled down further
+        let size = PLACEHOLDER_SIZE.max(PALETTE_SIZE).max(FINGERPRINT_SIZE);
+        let image = DynamicImage::ImageRgba8(self.preview(data, input_format, size)?);
+
+        Ok(appearance::new(
+            self.placeholder(&image)?,
+            self.palette(&image),
+            self.fingerprint(&image),
+        ))
+    }
+
+    fn placeholder(&self, image: &DynamicImage) -> Result<String> {
+        // a handful of components can't tell a tiny image from the full one, and
+        // encoding visits every pixel once per component
+        let image = image
+            .thumbnail(PLACEHOLDER_SIZE, PLACEHOLDER_SIZE)
+            .to_rgba8();
+        let (width, height) = image.dimensions();
+
+        // more components along the longer side keep the blur roughly square
+        let (x, y) = match width >= height {
+            true => (PLACEHOLDER_COMPONENTS.0, PLACEHOLDER_COMPONENTS.1),
+            false => (PLACEHOLDER_COMPONENTS.1, PLACEHOLDER_COMPONENTS.0),
+        };
+
+        blurhash::encode(x, y, width, height, image.as_raw())
+            .map_err(|e| anyhow!("could not encode blurhash: {}", e))
+    }
+
+    fn palette(&self, image: &DynamicImage) -> Vec<String> {
+        let image = image.thumbnail(PALETTE_SIZE, PALETTE_SIZE).to_rgba8();
+
+        palette::extract(&image, PALETTE_COLORS)
+    }
+
+    fn fingerprint(&self, image: &DynamicImage) -> u64 {
+        fingerprint::dhash(&image.thumbnail(FINGERPRINT_SIZE, FINGERPRINT_SIZE))
+    }
+
+    // the image scaled down to fit a square of the given size
+    fn preview(&self, data: &[u8], input_format: Format, size: u32) -> Result<RgbaImage> {
+        let image = match input_format {
+            Format::Svg => self
+                .svg
+                .rasterize(data, size, size)
+                .context("could not rasterize svg")?,
+            _ => self.load(data, input_format)?,
+        };
+
+        Ok(image.thumbnail(size, size).to_rgba8())
+    }
+
+    fn load(&self, data: &[u8], input_format: Format) -> Result<DynamicImage> {
         let image_format = match input_format {
             Format::Jpeg => image::ImageFormat::Jpeg,
             Format::Png => image::ImageFormat::Png,
@@ -37,16 +231,372 @@ impl Images for ImagesImpl {
             _ => bail!("unsupported image format: {:?}", input_format),
         };
 
-        let mut image = image::load_from_memory_with_format(data, image_format)
-            .context("could not load image")?;
+        // dimensions come from the header alone, so oversized images are rejected
+        // before the decoder allocates anything for them
+        let (width, height) = Reader::with_format(Cursor::new(data), image_format)
+            .into_dimensions()
+            .context("could not read image dimensions")?;
+
+        self.check_dimensions(width, height)?;
+
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(self.settings.max_image_width());
+        limits.max_image_height = Some(self.settings.max_image_height());
+        // room for 16 bit rgba, the widest format the decoders produce
+        limits.max_alloc = Some(self.settings.max_image_pixels() * 8);
+
+        let mut reader = Reader::with_format(Cursor::new(data), image_format);
+        reader.limits(limits);
+
+        let mut image = reader.decode().context("could not load image")?;
+
+        // variants are written without a profile, so pixels have to be srgb
+        if let Some(icc) = color::icc_profile(data) {
+            image = match self.color.to_srgb(&image, &icc) {
+                Ok(converted) => converted,
+                Err(e) => {
+                    tracing::warn!("could not convert color profile: {}", e);
+                    image
+                }
+            };
+        }
+
+        // cameras store pixels as captured and only tag which way is up
+        Ok(metadata::orient(image, metadata::orientation(data)))
+    }
+
+    fn check_dimensions(&self, width: u32, height: u32) -> Result<()> {
+        if width > self.settings.max_image_width()
+            || height > self.settings.max_image_height()
+            || width as u64 * height as u64 > self.settings.max_image_pixels()
+        {
+            return Err(media_too_large(format!("dimensions {}x{}", width, height)).into());
+        }
+
+        Ok(())
+    }
+
+    fn resize(&self, image: DynamicImage, profile: &Profile) -> DynamicImage {
+        let (nwidth, nheight) = (profile.width(), profile.height());
+        let filter = FilterType::CatmullRom;
+
+        match profile.fit() {
+            Fit::Contain => image.resize(nwidth, nheight, filter),
+            Fit::Cover => image.resize_to_fill(nwidth, nheight, filter),
+            Fit::Fill => image.resize_exact(nwidth, nheight, filter),
+        }
+    }
+
+    fn encode(&self, image: DynamicImage, profile: &Profile) -> Result<Vec<u8>> {
+        let mut buffer = Cursor::new(Vec::new());
+
+        match profile.format() {
+            Format::WebP => {
+                // libwebp only accepts 8 bit rgb(a)
+                let image = match image.color().has_alpha() {
+                    true => DynamicImage::ImageRgba8(image.to_rgba8()),
+                    false => DynamicImage::ImageRgb8(image.to_rgb8()),
+                };
+                WebPEncoder::new_with_quality(&mut buffer, WebPQuality::lossy(profile.quality()))
+                    .encode(
+                        image.as_bytes(),
+                        image.width(),
+                        image.height(),
+                        image.color(),
+                    )?
+            }
+            Format::Jpeg => {
+                // jpeg has no alpha channel
+                let image = image.to_rgb8();
+                JpegEncoder::new_with_quality(&mut buffer, profile.quality()).encode(
+                    image.as_raw(),
+                    image.width(),
+                    image.height(),
+                    ColorType::Rgb8,
+                )?
+            }
+            Format::Png => image.write_to(&mut buffer, ImageOutputFormat::Png)?,
+            Format::Avif => {
+                let image = image.to_rgba8();
+                let encoded = ravif::Encoder::new()
+                    .with_quality(profile.quality() as f32)
+                    .with_alpha_quality(profile.quality() as f32)
+                    .with_speed(self.settings.avif_speed())
+                    .encode_rgba(Img::new(
+                        image.as_raw().as_rgba(),
+                        image.width() as usize,
+                        image.height() as usize,
+                    ))
+                    .context("could not encode avif")?;
+                buffer.get_mut().extend(encoded.avif_file)
+            }
+            format => bail!("unsupported output format: {:?}", format),
+        };
+
+        Ok(buffer.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::{
+            errors::{self, MediaTooLarge},
+            profile,
+        },
+        gateways::{animation, color::tests::display_p3_icc, pool},
+        settings,
+    };
 
-        image = image.resize(nwidth, nheight, image::imageops::FilterType::CatmullRom);
+    fn images() -> impl Images {
+        new(Arc::new(settings::test()), Arc::new(pool::new(1)))
+    }
 
+    fn png(width: u32, height: u32) -> Vec<u8> {
         let mut buffer = Cursor::new(Vec::new());
-        image
-            .write_to(&mut buffer, image::ImageFormat::WebP)
-            .context("could not write image")?;
+        DynamicImage::new_rgb8(width, height)
+            .write_to(&mut buffer, ImageOutputFormat::Png)
+            .unwrap();
+        buffer.into_inner()
+    }
+
+    fn profile(fit: &str, format: &str) -> Variant {
+        let profile = serde_json::from_value::<Profile>(serde_json::json!({
+            "name": "test",
+            "width": 100,
+            "height": 100,
+            "fit": fit,
+            "format": format,
+        }))
+        .unwrap();
+        Variant::Profile(profile)
+    }
+
+    async fn dimensions(variant: Variant) -> (u32, u32, Format) {
+        let (data, format) = images()
+            .format(&png(400, 200), variant, Format::Png)
+            .await
+            .unwrap();
+        let image = image::load_from_memory(&data).unwrap();
+        (image.width(), image.height(), format)
+    }
+
+    #[tokio::test]
+    async fn contain_preserves_aspect_ratio() {
+        assert_eq!(
+            dimensions(profile("contain", "webp")).await,
+            (100, 50, Format::WebP)
+        );
+    }
+
+    #[tokio::test]
+    async fn cover_fills_the_box() {
+        assert_eq!(
+            dimensions(profile("cover", "jpeg")).await,
+            (100, 100, Format::Jpeg)
+        );
+    }
+
+    #[tokio::test]
+    async fn fill_stretches_to_the_box() {
+        assert_eq!(
+            dimensions(profile("fill", "png")).await,
+            (100, 100, Format::Png)
+        );
+    }
+
+    #[tokio::test]
+    async fn encodes_avif() {
+        let (data, format) = images()
+            .format(&png(400, 200), profile("contain", "avif"), Format::Png)
+            .await
+            .unwrap();
+
+        assert_eq!(format, Format::Avif);
+        assert_eq!(infer::get(&data).unwrap().mime_type(), "image/avif");
+    }
+
+    #[tokio::test]
+    async fn rasterizes_svg() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20"><rect width="40" height="20"/></svg>"#;
+
+        let (data, format) = images()
+            .format(svg, profile("contain", "webp"), Format::Svg)
+            .await
+            .unwrap();
+        let image = image::load_from_memory(&data).unwrap();
+
+        assert_eq!(format, Format::WebP);
+        assert_eq!((image.width(), image.height()), (100, 50));
+    }
+
+    #[tokio::test]
+    async fn converts_wide_gamut_to_srgb() {
+        let mut buffer = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 8, image::Rgb([200, 80, 60])))
+            .write_to(&mut buffer, ImageOutputFormat::Png)
+            .unwrap();
+        let untagged = buffer.into_inner();
+
+        let mut png = img_parts::png::Png::from_bytes(untagged.clone().into()).unwrap();
+        img_parts::ImageICC::set_icc_profile(&mut png, Some(display_p3_icc().into()));
+        let tagged = png.encoder().bytes().to_vec();
+
+        let mut pixels = Vec::new();
+        for data in [untagged, tagged] {
+            let (output, _) = images()
+                .format(&data, profile("fill", "png"), Format::Png)
+                .await
+                .unwrap();
+            let image = image::load_from_memory(&output).unwrap();
+            pixels.push(image.to_rgb8().get_pixel(50, 50).0);
+        }
+
+        // untagged pixels are taken as srgb already, p3 red is pushed further out
+        assert_eq!(pixels[0], [200, 80, 60]);
+        assert!(pixels[1][0] > 210 && pixels[1][1] < 80, "{:?}", pixels[1]);
+    }
+
+    #[tokio::test]
+    async fn describes_dimensions_as_displayed() {
+        let images = images();
+
+        let attributes = images.describe(&png(400, 200), Format::Png).await.unwrap();
+        assert_eq!(
+            (attributes.width(), attributes.height()),
+            (Some(400), Some(200))
+        );
+
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="40.5" height="20"/>"#;
+        let attributes = images.describe(svg, Format::Svg).await.unwrap();
+        assert_eq!(
+            (attributes.width(), attributes.height()),
+            (Some(41), Some(20))
+        );
+
+        let (avif, _) = images
+            .format(&png(400, 200), profile("contain", "avif"), Format::Png)
+            .await
+            .unwrap();
+        let attributes = images.describe(&avif, Format::Avif).await.unwrap();
+        assert_eq!(
+            (attributes.width(), attributes.height()),
+            (Some(100), Some(50))
+        );
+    }
+
+    #[tokio::test]
+    async fn computes_placeholders() {
+        let images = images();
+
+        let blurhash = images
+            .appearance(&png(400, 200), Format::Png)
+            .await
+            .unwrap()
+            .blurhash();
+        // size flag, max ac, dc and two characters for each of the 11 ac components
+        assert_eq!(blurhash.len(), 28);
+        assert!(blurhash::decode(&blurhash, 4, 2, 1.0).is_ok());
+
+        let animated = animation::tests::animated_webp();
+        assert!(images.appearance(&animated, Format::WebP).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn extracts_palettes() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20"><rect width="30" height="20" fill="red"/><rect x="30" width="10" height="20" fill="blue"/></svg>"#;
+
+        let palette = images()
+            .appearance(svg, Format::Svg)
+            .await
+            .unwrap()
+            .palette();
+
+        assert_eq!(palette.first().map(String::as_str), Some("#ff0000"));
+        assert!(palette.contains(&String::from("#0000ff")));
+    }
+
+    #[tokio::test]
+    async fn fingerprints_resized_copies_alike() {
+        let images = images();
+        let gradient = |from: &str, to: &str| {
+            format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="400" height="200"><linearGradient id="g"><stop offset="0" stop-color="{}"/><stop offset="1" stop-color="{}"/></linearGradient><rect width="400" height="200" fill="url(#g)"/><circle cx="100" cy="100" r="60" fill="white"/></svg>"#,
+                from, to
+            )
+        };
+        let svg = gradient("black", "yellow");
+
+        let (thumbnail, format) = images
+            .format(svg.as_bytes(), profile("contain", "webp"), Format::Svg)
+            .await
+            .unwrap();
+        let original = images
+            .appearance(svg.as_bytes(), Format::Svg)
+            .await
+            .unwrap()
+            .fingerprint();
+        let copy = images
+            .appearance(&thumbnail, format)
+            .await
+            .unwrap()
+            .fingerprint();
+        let reversed = images
+            .appearance(gradient("yellow", "black").as_bytes(), Format::Svg)
+            .await
+            .unwrap()
+            .fingerprint();
+
+        assert!((original ^ copy).count_ones() <= 4);
+        assert!((original ^ reversed).count_ones() > 16);
+    }
+
+    #[tokio::test]
+    async fn rejects_images_over_dimension_limits() {
+        let e = images()
+            .format(&png(10_000, 1), profile("contain", "webp"), Format::Png)
+            .await
+            .unwrap_err();
+
+        assert!(errors::find::<MediaTooLarge>(&e).is_some());
+    }
+
+    #[test]
+    fn limits_total_pixels() {
+        let processor = Processor {
+            settings: Arc::new(settings::test()),
+            svg: svg::new(),
+            color: color::new(),
+        };
+
+        assert!(processor.check_dimensions(8192, 4096).is_ok());
+        assert!(processor.check_dimensions(8192, 8192).is_err());
+        assert!(processor.check_dimensions(8193, 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_original_variant() {
+        let result = images()
+            .format(&png(10, 10), Variant::Original, Format::Png)
+            .await;
+
+        assert!(result.is_err());
+    }
 
-        Ok((buffer.into_inner(), Format::WebP))
+    #[test]
+    fn profile_defaults_to_webp_contain() {
+        assert_eq!(
+            profile("contain", "webp"),
+            Variant::Profile(
+                serde_json::from_value::<Profile>(serde_json::json!({
+                    "name": "test",
+                    "width": 100,
+                    "height": 100,
+                }))
+                .unwrap()
+            )
+        );
+        assert_eq!(profile::new("test", 100, 100).fit(), Fit::Contain);
     }
 }
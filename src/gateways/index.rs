@@ -0,0 +1,330 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::usecases::gateways::Index;
+
+// the log is rewritten with only the live entries once most of its lines are stale
+const MIN_COMPACTION_LINES: usize = 1000;
+
+// Fingerprints are kept in memory and every change is appended to a log that is
+// replayed on startup. Lookups scan every entry, which is fine for the thousands of
+// images a single instance holds.
+struct Local {
+    path: PathBuf,
+    // held across writes so the log is in the same order as the map
+    state: Mutex<State>,
+}
+
+struct State {
+    fingerprints: HashMap<String, u64>,
+    lines: usize,
+}
+
+// a removal is logged as an entry without a fingerprint
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    file_name: String,
+    fingerprint: Option<u64>,
+}
+
+pub fn new(path: PathBuf) -> Result<impl Index> {
+    // up front, so a path that can't be written to fails at startup rather than on
+    // the first upload
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).context("could not create index directory")?;
+    }
+
+    let state = load(&path)?;
+
+    Ok(Local {
+        path,
+        state: Mutex::new(state),
+    })
+}
+
+fn load(path: &Path) -> Result<State> {
+    let log = match fs::read_to_string(path) {
+        Ok(log) => log,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Ok(State {
+                fingerprints: HashMap::new(),
+                lines: 0,
+            })
+        }
+        Err(e) => return Err(e).context("could not read index"),
+    };
+
+    let mut fingerprints = HashMap::new();
+
+    for line in log.lines() {
+        let entry = match serde_json::from_str::<Entry>(line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                tracing::warn!("skipping index entry: {}", e);
+                continue;
+            }
+        };
+
+        match entry.fingerprint {
+            Some(fingerprint) => fingerprints.insert(entry.file_name, fingerprint),
+            None => fingerprints.remove(&entry.file_name),
+        };
+    }
+
+    let mut state = State {
+        lines: log.lines().count(),
+        fingerprints,
+    };
+
+    if state.is_stale() {
+        rewrite(path, &state.fingerprints)?;
+        state.lines = state.fingerprints.len();
+    } else if !log.is_empty() && !log.ends_with('\n') {
+        // a crash part way through an append leaves a partial last line, which is
+        // ended so the next append starts on a line of its own
+        append_line(path, b"\n").context("could not repair index")?;
+    }
+
+    Ok(state)
+}
+
+fn append_line(path: &Path, line: &[u8]) -> Result<()> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line))
+        .context("could not write index")
+}
+
+// written next to the log and renamed over it, so a crash leaves one or the other
+fn rewrite(path: &Path, fingerprints: &HashMap<String, u64>) -> Result<()> {
+    let mut entries = fingerprints.iter().collect::<Vec<_>>();
+    entries.sort();
+
+    let mut log = Vec::new();
+    for (file_name, fingerprint) in entries {
+        let entry = Entry {
+            file_name: file_name.clone(),
+            fingerprint: Some(*fingerprint),
+        };
+        serde_json::to_writer(&mut log, &entry).context("could not serialize index entry")?;
+        log.push(b'\n');
+    }
+
+    let compacted = path.with_extension("jsonl.tmp");
+    File::create(&compacted)
+        .and_then(|mut file| {
+            file.write_all(&log)?;
+            file.sync_all()
+        })
+        .context("could not write compacted index")?;
+    fs::rename(&compacted, path).context("could not replace index")
+}
+
+impl State {
+    fn is_stale(&self) -> bool {
+        self.lines >= MIN_COMPACTION_LINES && self.lines > 2 * self.fingerprints.len()
+    }
+}
+
+impl Local {
+    async fn append(&self, state: &mut State, entry: Entry) -> Result<()> {
+        let mut line = serde_json::to_vec(&entry).context("could not serialize index entry")?;
+        line.push(b'\n');
+
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || append_line(&path, &line))
+            .await
+            .context("index task failed")??;
+
+        match entry.fingerprint {
+            Some(fingerprint) => state.fingerprints.insert(entry.file_name, fingerprint),
+            None => state.fingerprints.remove(&entry.file_name),
+        };
+        state.lines += 1;
+
+        if state.is_stale() {
+            // the entry is already logged, a log that can't be compacted is just longer
+            if let Err(e) = self.compact(state).await {
+                tracing::warn!("could not compact index: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn compact(&self, state: &mut State) -> Result<()> {
+        let path = self.path.clone();
+        let fingerprints = state.fingerprints.clone();
+        tokio::task::spawn_blocking(move || rewrite(&path, &fingerprints))
+            .await
+            .context("index task failed")??;
+
+        state.lines = state.fingerprints.len();
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Index for Local {
+    async fn insert(&self, file_name: String, fingerprint: u64) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        if state.fingerprints.get(&file_name) == Some(&fingerprint) {
+            return Ok(());
+        }
+
+        self.append(
+            &mut state,
+            Entry {
+                file_name,
+                fingerprint: Some(fingerprint),
+            },
+        )
+        .await
+    }
+
+    async fn get(&self, file_name: String) -> Result<Option<u64>> {
+        Ok(self
+            .state
+            .lock()
+            .await
+            .fingerprints
+            .get(&file_name)
+            .copied())
+    }
+
+    async fn similar(&self, fingerprint: u64, distance: u32) -> Result<Vec<(String, u32)>> {
+        let mut similar = self
+            .state
+            .lock()
+            .await
+            .fingerprints
+            .iter()
+            .map(|(file_name, other)| (file_name.clone(), (fingerprint ^ other).count_ones()))
+            .filter(|(_, d)| *d <= distance)
+            .collect::<Vec<_>>();
+
+        similar.sort_by(|(a_name, a), (b_name, b)| a.cmp(b).then_with(|| a_name.cmp(b_name)));
+
+        Ok(similar)
+    }
+
+    async fn remove(&self, file_name: String) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        if !state.fingerprints.contains_key(&file_name) {
+            return Ok(());
+        }
+
+        self.append(
+            &mut state,
+            Entry {
+                file_name,
+                fingerprint: None,
+            },
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "images-index-{}-{}.jsonl",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn finds_fingerprints_within_distance_closest_first() {
+        let index = new(path("similar")).unwrap();
+        index.insert(String::from("a"), 0b0000).await.unwrap();
+        index.insert(String::from("b"), 0b0111).await.unwrap();
+        index.insert(String::from("c"), 0b0001).await.unwrap();
+
+        assert_eq!(
+            index.similar(0b0000, 2).await.unwrap(),
+            vec![(String::from("a"), 0), (String::from("c"), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn replays_the_log_on_startup() {
+        let path = path("replay");
+        let index = new(path.clone()).unwrap();
+        index.insert(String::from("a"), 1).await.unwrap();
+        index.insert(String::from("b"), 2).await.unwrap();
+        index.insert(String::from("a"), 3).await.unwrap();
+        index.remove(String::from("b")).await.unwrap();
+        drop(index);
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"file_na")
+            .unwrap();
+
+        let index = new(path.clone()).unwrap();
+        index.insert(String::from("c"), 4).await.unwrap();
+        drop(index);
+
+        let index = new(path.clone()).unwrap();
+        assert_eq!(index.get(String::from("a")).await.unwrap(), Some(3));
+        assert_eq!(index.get(String::from("b")).await.unwrap(), None);
+        assert_eq!(index.get(String::from("c")).await.unwrap(), Some(4));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn creates_missing_directories() {
+        let directory = path("directory");
+        let path = directory.join("nested").join("index.jsonl");
+
+        let index = new(path.clone()).unwrap();
+        index.insert(String::from("a"), 1).await.unwrap();
+
+        assert!(path.exists());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn compacts_the_log_once_mostly_stale() {
+        let path = path("compact");
+        let index = new(path.clone()).unwrap();
+        index.insert(String::from("a"), 1).await.unwrap();
+        for fingerprint in 0..MIN_COMPACTION_LINES as u64 {
+            index.insert(String::from("b"), fingerprint).await.unwrap();
+        }
+        drop(index);
+
+        assert!(fs::read_to_string(&path).unwrap().lines().count() < MIN_COMPACTION_LINES);
+        let index = new(path.clone()).unwrap();
+        assert_eq!(index.get(String::from("a")).await.unwrap(), Some(1));
+        assert_eq!(
+            index.get(String::from("b")).await.unwrap(),
+            Some(MIN_COMPACTION_LINES as u64 - 1)
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
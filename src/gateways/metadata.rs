@@ -0,0 +1,398 @@
+use std::io::Cursor;
+
+use anyhow::{bail, Context, Result};
+use exif::{experimental::Writer, Field, In, Tag, Value};
+use image::DynamicImage;
+use img_parts::{
+    jpeg::{markers, Jpeg, JpegSegment},
+    png::Png,
+    riff::{RiffChunk, RiffContent},
+    webp::{WebP, CHUNK_EXIF, CHUNK_VP8L, CHUNK_VP8X, CHUNK_XMP},
+    Bytes, ImageEXIF,
+};
+
+use crate::common::format::Format;
+
+const ICC_PREFIX: &[u8] = b"ICC_PROFILE\0";
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+
+// png chunks that carry text, timestamps or exif rather than pixels or color
+const PNG_METADATA_CHUNKS: [&[u8; 4]; 5] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+// webp extended header feature flags
+const VP8X_ALPHA: u8 = 0b0001_0000;
+const VP8X_EXIF: u8 = 0b0000_1000;
+const VP8X_XMP: u8 = 0b0000_0100;
+// lossless bitstreams mark alpha in the last byte of their header
+const VP8L_ALPHA: u8 = 0b0001_0000;
+
+// exif orientation, 1 is upright and anything else needs rotating and/or flipping
+pub fn orientation(data: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(Tag::Orientation, In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+        })
+        .filter(|orientation| (1..=8).contains(orientation))
+        .unwrap_or(1)
+}
+
+pub fn orient(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+// Drops exif, xmp, iptc and comments without re-encoding. Color profiles are kept,
+// and so is the orientation as a bare exif block, otherwise the original would be
+// displayed sideways.
+pub fn strip(data: &[u8], format: Format) -> Result<Vec<u8>> {
+    let exif = match orientation(data) {
+        1 => None,
+        orientation => Some(orientation_exif(orientation)?),
+    };
+    let bytes = Bytes::copy_from_slice(data);
+
+    let stripped = match format {
+        Format::Jpeg => {
+            let mut jpeg = Jpeg::from_bytes(bytes).context("could not parse jpeg")?;
+            jpeg.segments_mut().retain(keep_jpeg_segment);
+
+            if let Some(exif) = exif {
+                // exif belongs right after the jfif header
+                let position = jpeg
+                    .segments()
+                    .iter()
+                    .take_while(|segment| segment.marker() == markers::APP0)
+                    .count();
+                let contents = Bytes::from([EXIF_PREFIX, &exif].concat());
+                jpeg.segments_mut().insert(
+                    position,
+                    JpegSegment::new_with_contents(markers::APP1, contents),
+                );
+            }
+
+            jpeg.encoder().bytes()
+        }
+        Format::Png => {
+            let mut png = Png::from_bytes(bytes).context("could not parse png")?;
+            png.chunks_mut()
+                .retain(|chunk| !PNG_METADATA_CHUNKS.contains(&&chunk.kind()));
+            png.set_exif(exif.map(Bytes::from));
+
+            png.encoder().bytes()
+        }
+        Format::WebP => {
+            let mut webp = WebP::from_bytes(bytes).context("could not parse webp")?;
+            set_webp_exif(&mut webp, exif)?;
+
+            webp.encoder().bytes()
+        }
+        _ => bail!("can not strip metadata from {:?}", format),
+    };
+
+    Ok(stripped.to_vec())
+}
+
+// Replaces the exif and drops the xmp. img-parts would rebuild the header itself but
+// it only knows still images, animated and alpha files lose their extended header
+// once no exif or icc is left, so the chunks and flags are edited here instead.
+fn set_webp_exif(webp: &mut WebP, exif: Option<Vec<u8>>) -> Result<()> {
+    webp.remove_chunks_by_id(CHUNK_EXIF);
+    webp.remove_chunks_by_id(CHUNK_XMP);
+
+    if let Some(exif) = &exif {
+        // simple files have no room for metadata until they get an extended header
+        if !webp.has_chunk(CHUNK_VP8X) {
+            let header = extended_header(webp)?;
+            webp.chunks_mut().insert(0, header);
+        }
+
+        // the webp spec stores bare tiff, not the jpeg style prefix img-parts adds
+        webp.chunks_mut().push(RiffChunk::new(
+            CHUNK_EXIF,
+            RiffContent::Data(Bytes::from(exif.clone())),
+        ));
+    }
+
+    // icc, alpha and animation are left as they were
+    if let Some(header) = webp
+        .chunks_mut()
+        .iter_mut()
+        .find(|chunk| chunk.id() == CHUNK_VP8X)
+    {
+        let mut content = header
+            .content()
+            .data()
+            .filter(|data| data.len() == 10)
+            .context("invalid webp header")?
+            .to_vec();
+        content[0] &= !(VP8X_EXIF | VP8X_XMP);
+        if exif.is_some() {
+            content[0] |= VP8X_EXIF;
+        }
+
+        *header = RiffChunk::new(CHUNK_VP8X, RiffContent::Data(Bytes::from(content)));
+    }
+
+    Ok(())
+}
+
+fn extended_header(webp: &WebP) -> Result<RiffChunk> {
+    let (width, height) = webp
+        .dimensions()
+        .context("could not read webp dimensions")?;
+    let alpha = webp
+        .chunk_by_id(CHUNK_VP8L)
+        .and_then(|chunk| chunk.content().data())
+        .and_then(|data| data.get(4))
+        .is_some_and(|byte| byte & VP8L_ALPHA != 0);
+
+    let mut content = vec![if alpha { VP8X_ALPHA } else { 0 }, 0, 0, 0];
+    content.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    content.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+
+    Ok(RiffChunk::new(
+        CHUNK_VP8X,
+        RiffContent::Data(Bytes::from(content)),
+    ))
+}
+
+fn keep_jpeg_segment(segment: &JpegSegment) -> bool {
+    match segment.marker() {
+        // app2 is shared with other data such as multi picture thumbnails
+        markers::APP2 => segment.contents().starts_with(ICC_PREFIX),
+        // app0 is jfif and app14 adobe color transform, both needed to decode
+        markers::APP0 | markers::APP14 => true,
+        markers::APP1..=markers::APP15 | markers::COM => false,
+        _ => true,
+    }
+}
+
+fn orientation_exif(orientation: u32) -> Result<Vec<u8>> {
+    let field = Field {
+        tag: Tag::Orientation,
+        ifd_num: In::PRIMARY,
+        value: Value::Short(vec![orientation as u16]),
+    };
+
+    let mut writer = Writer::new();
+    writer.push_field(&field);
+
+    let mut buffer = Cursor::new(Vec::new());
+    writer
+        .write(&mut buffer, false)
+        .context("could not write exif")?;
+
+    Ok(buffer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{codecs::webp::WebPDecoder, AnimationDecoder, ImageOutputFormat, Rgb, RgbImage};
+    use img_parts::webp::CHUNK_ALPH;
+
+    use super::*;
+    use crate::{common::format, gateways::animation::tests::animated_webp};
+
+    // a 2x1 jpeg with gps, an xmp packet, a comment and an icc profile
+    fn tagged_jpeg(orientation: u32) -> Vec<u8> {
+        let mut buffer = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::from_fn(2, 1, |x, _| match x {
+            0 => Rgb([255, 0, 0]),
+            _ => Rgb([0, 0, 255]),
+        }))
+        .write_to(&mut buffer, ImageOutputFormat::Jpeg(100))
+        .unwrap();
+
+        let fields = [
+            Field {
+                tag: Tag::Orientation,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![orientation as u16]),
+            },
+            Field {
+                tag: Tag::GPSLatitudeRef,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"N".to_vec()]),
+            },
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut exif = Cursor::new(Vec::new());
+        writer.write(&mut exif, false).unwrap();
+
+        let mut jpeg = Jpeg::from_bytes(Bytes::from(buffer.into_inner())).unwrap();
+        let segments = [
+            (markers::APP1, [EXIF_PREFIX, &exif.into_inner()].concat()),
+            (
+                markers::APP1,
+                b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta/>".to_vec(),
+            ),
+            (markers::APP2, [ICC_PREFIX, b"\x01\x01profile"].concat()),
+            (markers::COM, b"taken at home".to_vec()),
+        ];
+        for (i, (marker, contents)) in segments.into_iter().enumerate() {
+            jpeg.segments_mut().insert(
+                1 + i,
+                JpegSegment::new_with_contents(marker, Bytes::from(contents)),
+            );
+        }
+
+        jpeg.encoder().bytes().to_vec()
+    }
+
+    #[test]
+    fn strips_jpeg_metadata_but_keeps_orientation_and_icc() {
+        let stripped = strip(&tagged_jpeg(6), Format::Jpeg).unwrap();
+
+        let exif = exif::Reader::new()
+            .read_from_container(&mut Cursor::new(&stripped))
+            .unwrap();
+        assert_eq!(exif.fields().count(), 1);
+        assert_eq!(orientation(&stripped), 6);
+
+        let jpeg = Jpeg::from_bytes(Bytes::from(stripped.clone())).unwrap();
+        assert!(jpeg.segment_by_marker(markers::APP2).is_some());
+        assert!(jpeg.segment_by_marker(markers::COM).is_none());
+        assert_eq!(jpeg.segments_by_marker(markers::APP1).count(), 1);
+
+        assert!(image::load_from_memory(&stripped).is_ok());
+    }
+
+    #[test]
+    fn drops_exif_entirely_when_upright() {
+        let stripped = strip(&tagged_jpeg(1), Format::Jpeg).unwrap();
+
+        let jpeg = Jpeg::from_bytes(Bytes::from(stripped)).unwrap();
+        assert!(jpeg.segment_by_marker(markers::APP1).is_none());
+    }
+
+    #[test]
+    fn applies_orientation() {
+        let data = tagged_jpeg(6);
+        let image = image::load_from_memory(&data).unwrap();
+
+        let oriented = orient(image, orientation(&data));
+
+        assert_eq!((oriented.width(), oriented.height()), (1, 2));
+        // rotated clockwise, so the left red pixel ends up on top
+        let top = oriented.to_rgb8().get_pixel(0, 0).0;
+        assert!(top[0] > 200 && top[2] < 50);
+    }
+
+    #[test]
+    fn strips_png_text_chunks() {
+        let mut buffer = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(1, 1)
+            .write_to(&mut buffer, ImageOutputFormat::Png)
+            .unwrap();
+        let mut png = Png::from_bytes(Bytes::from(buffer.into_inner())).unwrap();
+        let position = png.chunks().len() - 1;
+        png.chunks_mut().insert(
+            position,
+            img_parts::png::PngChunk::new(*b"tEXt", Bytes::from_static(b"Author\0me")),
+        );
+
+        let stripped = strip(&png.encoder().bytes(), Format::Png).unwrap();
+
+        let png = Png::from_bytes(Bytes::from(stripped)).unwrap();
+        assert!(png.chunk_by_type(*b"tEXt").is_none());
+        assert!(png.chunk_by_type(*b"IHDR").is_some());
+    }
+
+    #[test]
+    fn keeps_webp_orientation_as_bare_tiff() {
+        let mut buffer = Cursor::new(Vec::new());
+        image::codecs::webp::WebPEncoder::new_with_quality(
+            &mut buffer,
+            image::codecs::webp::WebPQuality::lossy(80),
+        )
+        .encode(&[0, 0, 0], 1, 1, image::ColorType::Rgb8)
+        .unwrap();
+        let mut webp = WebP::from_bytes(Bytes::from(buffer.into_inner())).unwrap();
+        set_webp_exif(&mut webp, Some(orientation_exif(8).unwrap())).unwrap();
+        webp.chunks_mut().push(RiffChunk::new(
+            CHUNK_XMP,
+            RiffContent::Data(Bytes::from_static(b"<x:xmpmeta/>")),
+        ));
+
+        let stripped = strip(&webp.encoder().bytes(), Format::WebP).unwrap();
+
+        assert_eq!(orientation(&stripped), 8);
+        let webp = WebP::from_bytes(Bytes::from(stripped.clone())).unwrap();
+        assert!(!webp.has_chunk(CHUNK_XMP));
+        assert!(image::load_from_memory(&stripped).is_ok());
+    }
+
+    fn vp8x_flags(data: &[u8]) -> u8 {
+        let webp = WebP::from_bytes(Bytes::copy_from_slice(data)).unwrap();
+        webp.chunk_by_id(CHUNK_VP8X)
+            .unwrap()
+            .content()
+            .data()
+            .unwrap()[0]
+    }
+
+    fn with_xmp(data: &[u8]) -> Vec<u8> {
+        let mut webp = WebP::from_bytes(Bytes::copy_from_slice(data)).unwrap();
+        webp.chunks_mut().push(RiffChunk::new(
+            CHUNK_XMP,
+            RiffContent::Data(Bytes::from_static(b"<x:xmpmeta/>")),
+        ));
+        webp.encoder().bytes().to_vec()
+    }
+
+    #[test]
+    fn keeps_animated_webp_intact() {
+        let animated = with_xmp(&animated_webp());
+
+        let stripped = strip(&animated, Format::WebP).unwrap();
+
+        assert!(format::is_animated_webp(&stripped));
+        assert_eq!(vp8x_flags(&stripped) & (VP8X_EXIF | VP8X_XMP), 0);
+        let decoder = WebPDecoder::new(Cursor::new(&stripped)).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].buffer().get_pixel(0, 0).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn keeps_webp_alpha_intact() {
+        let mut buffer = Cursor::new(Vec::new());
+        image::codecs::webp::WebPEncoder::new_with_quality(
+            &mut buffer,
+            image::codecs::webp::WebPQuality::lossy(80),
+        )
+        .encode(
+            &[255, 0, 0, 0, 255, 0, 0, 255],
+            2,
+            1,
+            image::ColorType::Rgba8,
+        )
+        .unwrap();
+        let transparent = with_xmp(&buffer.into_inner());
+        let webp = WebP::from_bytes(Bytes::from(transparent.clone())).unwrap();
+        assert!(webp.has_chunk(CHUNK_ALPH));
+
+        let stripped = strip(&transparent, Format::WebP).unwrap();
+
+        assert_ne!(vp8x_flags(&stripped) & VP8X_ALPHA, 0);
+        // image can't read libwebp's compressed alpha, libwebp decodes it instead
+        let decoded = webp::Decoder::new(&stripped).decode().unwrap();
+        assert!(decoded.is_alpha());
+        assert_eq!(decoded[3], 0);
+        assert_eq!(decoded[7], 255);
+    }
+}
@@ -1,4 +1,13 @@
+pub mod animation;
+pub mod color;
+pub mod fingerprint;
+pub mod fs;
 pub mod http;
 pub mod images;
+pub mod index;
+pub mod metadata;
+pub mod palette;
+pub mod pool;
 pub mod s3;
+pub mod svg;
 pub mod video;
@@ -0,0 +1,114 @@
+use image::RgbaImage;
+
+// pixels this transparent don't contribute to how the image looks
+const MIN_ALPHA: u8 = 128;
+
+// Median cut: the box of colors with the widest channel is split at its median until
+// there are enough boxes, each box then averages to one color. Deterministic, so the
+// same image always gets the same palette. Most common color first.
+pub fn extract(image: &RgbaImage, colors: usize) -> Vec<String> {
+    let pixels = image
+        .pixels()
+        .filter(|pixel| pixel[3] >= MIN_ALPHA)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect::<Vec<[u8; 3]>>();
+
+    if pixels.is_empty() || colors == 0 {
+        return Vec::new();
+    }
+
+    let mut boxes = vec![pixels];
+
+    while boxes.len() < colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, pixels)| (i, widest_channel(pixels)))
+            .filter(|(_, (_, range))| *range > 0)
+            .max_by_key(|(_, (_, range))| *range);
+
+        // every box is a single color already
+        let (i, (channel, _)) = match widest {
+            Some(widest) => widest,
+            None => break,
+        };
+
+        let mut pixels = boxes.swap_remove(i);
+        pixels.sort_unstable_by_key(|pixel| pixel[channel]);
+
+        // split where the value changes closest to the median, cutting a run of the
+        // same value in two would give the same color twice
+        let median = pixels.len() / 2;
+        let value = pixels[median][channel];
+        let below = pixels.partition_point(|pixel| pixel[channel] < value);
+        let above = pixels.partition_point(|pixel| pixel[channel] <= value);
+        let split = match (below, above) {
+            (0, above) => above,
+            (below, above) if above == pixels.len() => below,
+            (below, above) if median - below <= above - median => below,
+            (_, above) => above,
+        };
+        let upper = pixels.split_off(split);
+
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+
+    boxes.sort_by_key(|pixels| std::cmp::Reverse(pixels.len()));
+
+    boxes.iter().map(|pixels| hex(average(pixels))).collect()
+}
+
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let (min, max) = pixels.iter().fold((u8::MAX, u8::MIN), |(min, max), pixel| {
+                (min.min(pixel[channel]), max.max(pixel[channel]))
+            });
+            (channel, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+fn average(pixels: &[[u8; 3]]) -> [u8; 3] {
+    let mut sums = [0u64; 3];
+    for pixel in pixels {
+        for channel in 0..3 {
+            sums[channel] += pixel[channel] as u64;
+        }
+    }
+
+    sums.map(|sum| (sum / pixels.len() as u64) as u8)
+}
+
+fn hex([red, green, blue]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", red, green, blue)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn orders_colors_by_how_common_they_are() {
+        // three quarters red, a quarter blue, and a transparent green column
+        let image = RgbaImage::from_fn(5, 4, |x, _| match x {
+            0..=2 => Rgba([255, 0, 0, 255]),
+            3 => Rgba([0, 0, 255, 255]),
+            _ => Rgba([0, 255, 0, 0]),
+        });
+
+        assert_eq!(extract(&image, 5), vec!["#ff0000", "#0000ff"]);
+    }
+
+    #[test]
+    fn limits_the_number_of_colors() {
+        let image = RgbaImage::from_fn(16, 16, |x, y| Rgba([x as u8 * 16, y as u8 * 16, 0, 255]));
+
+        assert_eq!(extract(&image, 5).len(), 5);
+        assert!(extract(&RgbaImage::new(4, 4), 5).is_empty());
+    }
+}
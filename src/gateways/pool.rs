@@ -0,0 +1,189 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::common::errors::overloaded;
+
+// bounds how much of a kind of work runs at once, callers over the limit wait their turn
+pub struct Pool {
+    permits: Arc<Semaphore>,
+    capacity: usize,
+    queued: Arc<AtomicUsize>,
+    // callers beyond this are turned away instead of waiting, unbounded if none
+    max_queued: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    capacity: usize,
+    running: usize,
+    queued: usize,
+}
+
+pub fn new(capacity: usize) -> Pool {
+    Pool {
+        permits: Arc::new(Semaphore::new(capacity)),
+        capacity,
+        queued: Arc::new(AtomicUsize::new(0)),
+        max_queued: None,
+    }
+}
+
+pub fn bounded(capacity: usize, max_queued: usize) -> Pool {
+    Pool {
+        max_queued: Some(max_queued),
+        ..new(capacity)
+    }
+}
+
+impl Pool {
+    // runs cpu heavy work off the async workers once a slot is free
+    pub async fn run_blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = self.acquire().await?;
+
+        // the permit moves into the task so a cancelled caller can't free the slot
+        // while the work is still running
+        tokio::task::spawn_blocking(move || {
+            let result = f();
+            drop(permit);
+            result
+        })
+        .await
+        .context("blocking task failed")?
+    }
+
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        let _queued = Queued::new(self.queued.clone());
+
+        if let Some(max_queued) = self.max_queued {
+            // the count already includes this caller
+            if self.queued.load(Ordering::Relaxed) > max_queued {
+                return Err(overloaded(format!("more than {} waiting", max_queued)).into());
+            }
+        }
+
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .context("pool is closed")
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            capacity: self.capacity,
+            running: self.capacity - self.permits.available_permits(),
+            queued: self.queued.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// counts a waiting caller for as long as it waits, including when it gives up early
+struct Queued {
+    queued: Arc<AtomicUsize>,
+}
+
+impl Queued {
+    fn new(queued: Arc<AtomicUsize>) -> Queued {
+        queued.fetch_add(1, Ordering::Relaxed);
+        Queued { queued }
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::errors::{self, Overloaded};
+
+    // yields to the spawned tasks until they have reached the expected state
+    async fn until(condition: impl Fn() -> bool) {
+        while !condition() {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn queues_work_over_capacity() {
+        let pool = Arc::new(new(1));
+        let (started_sender, started) = tokio::sync::oneshot::channel::<()>();
+        let (sender, receiver) = std::sync::mpsc::channel::<()>();
+
+        let running = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                pool.run_blocking(move || {
+                    started_sender.send(()).unwrap();
+                    receiver.recv().unwrap();
+                    Ok(1)
+                })
+                .await
+            }
+        });
+        started.await.unwrap();
+
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run_blocking(|| Ok(2)).await }
+        });
+
+        until(|| pool.stats().queued == 1).await;
+        assert_eq!(
+            pool.stats(),
+            Stats {
+                capacity: 1,
+                running: 1,
+                queued: 1
+            }
+        );
+
+        sender.send(()).unwrap();
+        assert_eq!(running.await.unwrap().unwrap(), 1);
+        assert_eq!(waiting.await.unwrap().unwrap(), 2);
+        assert_eq!(
+            pool.stats(),
+            Stats {
+                capacity: 1,
+                running: 0,
+                queued: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_callers_over_the_queue_bound() {
+        let pool = Arc::new(bounded(1, 1));
+        let running = pool.acquire().await.unwrap();
+
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.acquire().await.map(|_| ()) }
+        });
+        until(|| pool.stats().queued == 1).await;
+
+        let e = pool.acquire().await.unwrap_err();
+        assert!(errors::find::<Overloaded>(&e).is_some());
+        assert_eq!(pool.stats().queued, 1);
+
+        drop(running);
+        assert!(waiting.await.unwrap().is_ok());
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{common::variant::Variant, settings::Settings, usecases::gateways::Storage};
+use crate::{
+    common::{attributes::Attributes, variant::Variant},
+    settings::Settings,
+    usecases::gateways::Storage,
+};
 use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
 use aws_sdk_s3::{config::Region, error::SdkError, primitives::ByteStream, Client};
@@ -28,6 +32,7 @@ impl Storage for S3 {
         variant: Variant,
         content_type: String,
         body: Vec<u8>,
+        attributes: Attributes,
     ) -> Result<String> {
         let bucket = self.settings.bucket();
         let key = self.get_key(file_name.clone(), variant);
@@ -38,6 +43,7 @@ impl Storage for S3 {
             .key(key.clone())
             .content_type(content_type)
             .cache_control("max-age=31536000") // 1yr
+            .set_metadata(Some(attributes.to_metadata()?))
             .body(ByteStream::from(body))
             .send()
             .await
@@ -46,7 +52,11 @@ impl Storage for S3 {
         Ok(self.get_external_url(key))
     }
 
-    async fn get(&self, variant: Variant, file_name: String) -> Result<Option<(String, String)>> {
+    async fn get(
+        &self,
+        variant: Variant,
+        file_name: String,
+    ) -> Result<Option<(String, String, Attributes)>> {
         let bucket = self.settings.bucket();
         let key = self.get_key(file_name.clone(), variant);
 
@@ -75,19 +85,123 @@ impl Storage for S3 {
             .content_type()
             .ok_or(anyhow!("could not get content type for {}", key))?
             .to_string();
+        let attributes = Attributes::from_metadata(header.metadata())
+            .with_context(|| format!("could not read attributes for {}", key))?;
         let url = self.get_external_url(key);
 
-        Ok(Some((url, content_type)))
+        Ok(Some((url, content_type, attributes)))
+    }
+
+    async fn delete(&self, variant: Variant, file_name: String) -> Result<Option<String>> {
+        let bucket = self.settings.bucket();
+        let key = self.get_key(file_name.clone(), variant.clone());
+
+        // s3 deletes are idempotent so check first to know if anything was removed
+        if self.get(variant, file_name).await?.is_none() {
+            return Ok(None);
+        }
+
+        self.client
+            .delete_object()
+            .bucket(bucket)
+            .key(key.clone())
+            .send()
+            .await
+            .context("could not delete image")?;
+
+        Ok(Some(key))
+    }
+
+    async fn download(
+        &self,
+        variant: Variant,
+        file_name: String,
+    ) -> Result<Option<(Vec<u8>, String)>> {
+        let bucket = self.settings.bucket();
+        let key = self.get_key(file_name.clone(), variant);
+
+        let object = match self
+            .client
+            .get_object()
+            .bucket(bucket)
+            .key(key.clone())
+            .send()
+            .await
+        {
+            Ok(object) => object,
+            Err(e) => match e {
+                SdkError::ServiceError(err) => {
+                    if err.err().is_no_such_key() {
+                        return Ok(None);
+                    } else {
+                        bail!("could not download image: {}", err.err());
+                    }
+                }
+                _ => bail!("could not download image: {}", e),
+            },
+        };
+
+        let content_type = object
+            .content_type()
+            .ok_or(anyhow!("could not get content type for {}", key))?
+            .to_string();
+        let body = object
+            .body
+            .collect()
+            .await
+            .context("could not read image body")?
+            .into_bytes()
+            .to_vec();
+
+        Ok(Some((body, content_type)))
+    }
+
+    async fn delete_transforms(&self, file_name: String) -> Result<Vec<String>> {
+        let bucket = self.settings.bucket();
+        let prefix = format!("{}/", Variant::transforms_prefix(&file_name));
+        let mut deleted = Vec::new();
+        let mut continuation_token = None;
+
+        loop {
+            let page = self
+                .client
+                .list_objects_v2()
+                .bucket(bucket.clone())
+                .prefix(prefix.clone())
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .context("could not list transforms")?;
+
+            for object in page.contents().unwrap_or_default() {
+                let key = object
+                    .key()
+                    .ok_or(anyhow!("could not get key for transform"))?;
+
+                self.client
+                    .delete_object()
+                    .bucket(bucket.clone())
+                    .key(key)
+                    .send()
+                    .await
+                    .context("could not delete transform")?;
+
+                deleted.push(key.to_string());
+            }
+
+            continuation_token = page.next_continuation_token().map(String::from);
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(deleted)
     }
 }
 
 impl S3 {
     fn get_key(&self, id: String, variant: Variant) -> String {
-        match variant {
-            Variant::Thumbnail => format!("images/thumbnails/{}", id),
-            Variant::Original => format!("images/originals/{}", id),
-            Variant::Avatar => format!("images/avatars/{}", id),
-        }
+        variant.key(&id)
     }
 
     fn get_external_url(&self, key: String) -> String {
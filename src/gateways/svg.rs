@@ -0,0 +1,272 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Cursor,
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, RgbaImage};
+use resvg::{
+    tiny_skia::{Pixmap, Transform},
+    usvg::{
+        self, fontdb,
+        roxmltree::{self, Document, Node, NodeId},
+        ImageHrefResolver, ImageKind, PostProcessingSteps, TreeParsing, TreePostProc, TreeWriting,
+        XmlOptions,
+    },
+};
+
+const MAX_SVG_BYTES: usize = 1024 * 1024;
+// counted after expanding <use> references so nested reuse can't multiply the work
+const MAX_SVG_NODES: u64 = 20_000;
+const MAX_SVG_DEPTH: usize = 256;
+// bounds both rasterized output and embedded raster images
+const MAX_SVG_DIMENSION: u32 = 4096;
+
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+pub struct Svg {
+    fontdb: fontdb::Database,
+}
+
+pub fn new() -> Svg {
+    let mut fontdb = fontdb::Database::new();
+    fontdb.load_system_fonts();
+
+    Svg { fontdb }
+}
+
+impl Svg {
+    // Rasterizes the svg at a scale that covers the given box, leaving the final
+    // fit to the regular resize so every fit mode behaves the same as for bitmaps.
+    pub fn rasterize(&self, data: &[u8], width: u32, height: u32) -> Result<DynamicImage> {
+        let mut tree = self.parse(data)?;
+        tree.postprocess(PostProcessingSteps::default(), &self.fontdb);
+
+        let (svg_width, svg_height) = (tree.size.width(), tree.size.height());
+        let max = MAX_SVG_DIMENSION as f32;
+        let scale = (width as f32 / svg_width)
+            .max(height as f32 / svg_height)
+            .min(max / svg_width)
+            .min(max / svg_height);
+
+        let pwidth = ((svg_width * scale).ceil() as u32).clamp(1, MAX_SVG_DIMENSION);
+        let pheight = ((svg_height * scale).ceil() as u32).clamp(1, MAX_SVG_DIMENSION);
+
+        let mut pixmap = Pixmap::new(pwidth, pheight).context("could not allocate pixmap")?;
+        resvg::render(
+            &tree,
+            Transform::from_scale(scale, scale),
+            &mut pixmap.as_mut(),
+        );
+
+        // tiny skia stores premultiplied alpha
+        let pixels = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let color = pixel.demultiply();
+                [color.red(), color.green(), color.blue(), color.alpha()]
+            })
+            .collect();
+
+        let image = RgbaImage::from_raw(pwidth, pheight, pixels)
+            .context("could not create image from pixmap")?;
+
+        Ok(DynamicImage::ImageRgba8(image))
+    }
+
+    // intrinsic size in pixels, rounded up
+    pub fn size(&self, data: &[u8]) -> Result<(u32, u32)> {
+        let tree = self.parse(data)?;
+
+        Ok((
+            tree.size.width().ceil() as u32,
+            tree.size.height().ceil() as u32,
+        ))
+    }
+
+    // Round trips the svg through usvg, which only writes back what it can render:
+    // no scripts, event handlers, foreign objects or external references.
+    pub fn sanitize(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let tree = self.parse(data)?;
+
+        Ok(tree.to_string(&XmlOptions::default()).into_bytes())
+    }
+
+    fn parse(&self, data: &[u8]) -> Result<usvg::Tree> {
+        if data.len() > MAX_SVG_BYTES {
+            bail!("svg too large {}", data.len());
+        }
+
+        let text = std::str::from_utf8(data).context("svg is not utf8")?;
+
+        let doc = Document::parse_with_options(
+            text,
+            // entity definitions can expand a small file into a huge one, and svgs
+            // have no real use for a dtd
+            roxmltree::ParsingOptions {
+                allow_dtd: false,
+                nodes_limit: MAX_SVG_NODES as u32,
+            },
+        )
+        .context("could not parse svg")?;
+
+        let ids = doc
+            .descendants()
+            .filter_map(|node| node.attribute("id").map(|id| (id, node)))
+            .collect::<HashMap<&str, Node>>();
+        let mut sizes = HashMap::new();
+        let mut visiting = HashSet::new();
+
+        expanded_size(doc.root_element(), 0, &ids, &mut sizes, &mut visiting)?;
+
+        usvg::Tree::from_xmltree(&doc, &options()).context("could not convert svg")
+    }
+}
+
+fn options() -> usvg::Options {
+    usvg::Options {
+        resources_dir: None,
+        image_href_resolver: ImageHrefResolver {
+            resolve_data: Box::new(|_, data, _| embedded_image(data)),
+            // anything that isn't a data url is a file path or remote url
+            resolve_string: Box::new(|_, _| None),
+        },
+        ..usvg::Options::default()
+    }
+}
+
+// only bitmaps of bounded size may be embedded, nested svgs are dropped
+fn embedded_image(data: Arc<Vec<u8>>) -> Option<ImageKind> {
+    let reader = image::io::Reader::new(Cursor::new(data.as_slice()))
+        .with_guessed_format()
+        .ok()?;
+    let format = reader.format()?;
+    let (width, height) = reader.into_dimensions().ok()?;
+
+    if width > MAX_SVG_DIMENSION || height > MAX_SVG_DIMENSION {
+        return None;
+    }
+
+    match format {
+        image::ImageFormat::Png => Some(ImageKind::PNG(data)),
+        image::ImageFormat::Jpeg => Some(ImageKind::JPEG(data)),
+        image::ImageFormat::Gif => Some(ImageKind::GIF(data)),
+        _ => None,
+    }
+}
+
+// number of nodes the tree would have once every <use> is replaced by its target
+fn expanded_size<'a>(
+    node: Node<'a, 'a>,
+    depth: usize,
+    ids: &HashMap<&str, Node<'a, 'a>>,
+    sizes: &mut HashMap<NodeId, u64>,
+    visiting: &mut HashSet<NodeId>,
+) -> Result<u64> {
+    if let Some(size) = sizes.get(&node.id()) {
+        return Ok(*size);
+    }
+
+    if depth > MAX_SVG_DEPTH {
+        bail!("svg is nested too deeply");
+    }
+
+    if !visiting.insert(node.id()) {
+        bail!("svg contains a reference cycle");
+    }
+
+    let mut size: u64 = 1;
+
+    for child in node.children() {
+        size = size.saturating_add(expanded_size(child, depth + 1, ids, sizes, visiting)?);
+    }
+
+    if node.has_tag_name("use") {
+        let target = node
+            .attribute((XLINK_NS, "href"))
+            .or(node.attribute("href"))
+            .and_then(|href| href.strip_prefix('#'))
+            .and_then(|id| ids.get(id));
+
+        if let Some(target) = target {
+            size = size.saturating_add(expanded_size(*target, depth + 1, ids, sizes, visiting)?);
+        }
+    }
+
+    if size > MAX_SVG_NODES {
+        bail!("svg is too complex");
+    }
+
+    visiting.remove(&node.id());
+    sizes.insert(node.id(), size);
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="20">
+        <script>alert(1)</script>
+        <rect width="10" height="20" fill="red" onclick="alert(1)"/>
+        <image href="http://169.254.169.254/latest" width="10" height="10"/>
+        <image href="/etc/passwd" width="10" height="10"/>
+    </svg>"#;
+
+    #[test]
+    fn rasterizes_to_cover_the_box() {
+        let image = new().rasterize(SQUARE.as_bytes(), 100, 100).unwrap();
+
+        assert_eq!((image.width(), image.height()), (100, 200));
+        assert_eq!(image.to_rgba8().get_pixel(50, 50).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn sanitize_strips_scripts_and_external_references() {
+        let sanitized = String::from_utf8(new().sanitize(SQUARE.as_bytes()).unwrap()).unwrap();
+
+        assert!(sanitized.contains("<path"));
+        assert!(!sanitized.contains("script"));
+        assert!(!sanitized.contains("onclick"));
+        assert!(!sanitized.contains("169.254.169.254"));
+        assert!(!sanitized.contains("passwd"));
+    }
+
+    #[test]
+    fn rejects_exponential_use_expansion() {
+        let mut svg = String::from(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"><defs><rect id="l0" width="1" height="1"/>"##,
+        );
+        for i in 1..20 {
+            svg.push_str(&format!(r##"<g id="l{}">"##, i));
+            for _ in 0..10 {
+                svg.push_str(&format!(r##"<use xlink:href="#l{}"/>"##, i - 1));
+            }
+            svg.push_str("</g>");
+        }
+        svg.push_str(r##"</defs><use xlink:href="#l19"/></svg>"##);
+
+        assert!(new().sanitize(svg.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_dtds() {
+        let svg = r#"<?xml version="1.0"?>
+<!DOCTYPE svg [<!ENTITY a "aaaaaaaaaa"><!ENTITY b "&a;&a;&a;&a;&a;&a;&a;&a;&a;&a;">]>
+<svg xmlns="http://www.w3.org/2000/svg"><text>&b;</text></svg>"#;
+
+        assert!(new().sanitize(svg.as_bytes()).is_err());
+        assert!(new().rasterize(svg.as_bytes(), 10, 10).is_err());
+    }
+
+    #[test]
+    fn rejects_reference_cycles() {
+        let svg =
+            r##"<svg xmlns="http://www.w3.org/2000/svg"><g id="a"><use href="#a"/></g></svg>"##;
+
+        assert!(new().sanitize(svg.as_bytes()).is_err());
+    }
+}
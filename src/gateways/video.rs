@@ -1,85 +1,184 @@
 use std::{
+    ffi::OsString,
     fs,
-    io::Read,
-    io::Write,
+    io::ErrorKind,
     path::PathBuf,
+    process::Stdio,
+    sync::Arc,
     time::{Duration, SystemTime},
 };
 
 use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
-use tokio::process::Command;
+use serde::Deserialize;
+use tokio::{io::AsyncWriteExt, process::Command, time::timeout};
 
 use crate::{
-    common::{format::Format, variant::Variant},
-    usecases::gateways::Video,
+    common::{
+        attributes::{self, Attributes},
+        errors::{invalid_media, media_too_large},
+        format::Format,
+        profile::{Fit, Profile},
+        variant::Variant,
+    },
+    usecases::gateways::{Formatted, Video},
 };
 
-struct VideoImpl {}
+use super::{animation, pool::Pool};
 
-pub fn new() -> impl Video {
-    VideoImpl {}
+struct VideoImpl {
+    // every ffmpeg process holds a slot, so a burst of uploads queues instead of
+    // running them all at once
+    pool: Arc<Pool>,
+    // animated webp is decoded into frames here first, the same cpu bound work as
+    // any other image
+    image_pool: Arc<Pool>,
+}
+
+pub fn new(pool: Arc<Pool>, image_pool: Arc<Pool>) -> impl Video {
+    VideoImpl { pool, image_pool }
 }
 
 const DIRECTORY: &str = "/tmp/daochan";
 
+// inputs beyond these are rejected before ffmpeg ever decodes them
+const MAX_INPUT_DURATION_SECONDS: f64 = 120.0;
+pub(super) const MAX_INPUT_DIMENSION: u32 = 4096;
+pub(super) const MAX_INPUT_FRAMES: u64 = 3000;
+// pixels across all frames, roughly what decodes within the process timeout, e.g. 15s of
+// 1080p at 30fps. The limits above each allow far more on their own.
+pub(super) const MAX_INPUT_PIXELS: u64 = 1_000_000_000;
+
+// kept below the http request timeout together, so no process outlives its request
+const MAX_PROBE_SECONDS: u64 = 5;
+const MAX_PROCESS_SECONDS: u64 = 20;
+const MAX_POSTER_SECONDS: u64 = 3;
+
 #[async_trait]
 impl Video for VideoImpl {
-    // TODO:
-    // - should variant influence certain params?
     async fn format(
         &self,
         data: &[u8],
-        _variant: Variant,
+        variant: Variant,
         input_format: Format,
-    ) -> Result<(Vec<u8>, Format)> {
-        let input_path = self.get_path(uuid::Uuid::new_v4().to_string(), input_format);
+    ) -> Result<(Formatted, Option<(Variant, Formatted)>)> {
+        let poster_variant = variant.poster();
+        let profile = match variant {
+            Variant::Profile(profile) => profile,
+            other => bail!("{} variant can not be transcoded", other.name()),
+        };
+        let video = profile.video();
+
+        let _permit = self.pool.acquire().await?;
 
-        let output_path = self.get_path(uuid::Uuid::new_v4().to_string(), Format::Mp4);
+        let input = Input::new(
+            data,
+            input_format,
+            video.fps(),
+            video.max_duration(),
+            (profile.width(), profile.height()),
+            &self.image_pool,
+        )
+        .await?;
 
-        self.write(&input_path, &data.to_vec())?;
+        self.probe(&input, data).await?.check()?;
 
-        let mut child = Command::new("ffmpeg")
-            .arg("-i")
-            .arg(&input_path)
-            .arg("-y") // overwrite output file if it exists
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(input.args())
             .arg("-hide_banner")
             .arg("-loglevel")
             .arg("error")
             .arg("-an") // no audio
+            .arg("-t") // max duration in seconds
+            .arg(video.max_duration().to_string())
             .arg("-r") // frame rate
-            .arg("16")
-            .arg("-crf") // quality
-            .arg("23")
-            .arg("-preset") // speed
-            .arg("slow")
-            .arg("-c:v") // codec
-            .arg("libx264")
-            .arg("-movflags") // fast start
-            .arg("+faststart")
-            .arg("-pix_fmt") // pixel format
-            .arg("yuv420p") // required for safari and firefox
-            .arg("-vf") // video filter
-            .arg("pad=width=ceil(iw/2)*2:height=ceil(ih/2)*2") // make dimensions even (required for yuv420p I think)
-            .arg(&output_path)
-            .spawn()
-            .context("could not spawn video process")?;
+            .arg(video.fps().to_string());
+
+        let output_format = video.format();
+        match output_format {
+            Format::WebP => command
+                .arg("-c:v") // codec
+                .arg("libwebp_anim")
+                .arg("-quality") // quality
+                .arg(profile.quality().to_string())
+                .arg("-loop") // forever, like gif
+                .arg("0")
+                .arg("-vf") // video filter
+                .arg(self.scale(&profile))
+                .arg("-f")
+                .arg("webp"),
+            _ => command
+                .arg("-crf") // quality
+                .arg(video.crf().to_string())
+                .arg("-preset") // speed
+                .arg("slow")
+                .arg("-c:v") // codec
+                .arg("libx264")
+                .arg("-movflags") // fragmented so it can be written to a pipe, moov up front for fast start
+                .arg("frag_keyframe+empty_moov+default_base_moof")
+                .arg("-pix_fmt") // pixel format
+                .arg("yuv420p") // required for safari and firefox
+                .arg("-vf") // video filter
+                .arg(format!(
+                    "{},pad=width=ceil(iw/2)*2:height=ceil(ih/2)*2", // make dimensions even (required for yuv420p I think)
+                    self.scale(&profile)
+                ))
+                .arg("-f")
+                .arg("mp4"),
+        };
+        command.arg("pipe:1");
 
-        let status = child.wait().await.context("video process errored")?;
+        let buffer = self
+            .run(&mut command, input.stdin(data), MAX_PROCESS_SECONDS)
+            .await
+            .context("video process failed")?;
+
+        // after the transcode rather than beside it, a permit is one process at a time
+        let poster = match poster_variant {
+            Some(poster_variant) => match self.poster(&input, data, &profile).await {
+                Ok(poster) => Some((poster_variant, poster)),
+                Err(e) => {
+                    tracing::warn!("could not extract poster: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
 
-        if !status.success() {
-            bail!("video process exited with status: {}", status);
+        Ok(((buffer, output_format), poster))
+    }
+
+    async fn describe(&self, data: &[u8], input_format: Format) -> Result<Attributes> {
+        // ffmpeg can't read animated webp, its headers say as much as a probe would
+        if input_format == Format::WebP {
+            return animation::describe(data);
         }
 
-        let buffer = self.read(&output_path)?;
+        // only demuxes for a few seconds at most, taking a transcode slot would leave
+        // uploads without attributes whenever transcodes queue up
+        let input = Input::new(
+            data,
+            input_format,
+            1,
+            1,
+            (MAX_INPUT_DIMENSION, MAX_INPUT_DIMENSION),
+            &self.image_pool,
+        )
+        .await?;
 
-        Ok((buffer, Format::Mp4))
+        Ok(self.probe(&input, data).await?.attributes())
     }
 
+    // temp files remove themselves, this only catches what a crashed process left behind
     async fn clean(&self, stale_seconds: u64) -> Result<()> {
         let now = SystemTime::now();
 
-        let entries = fs::read_dir(PathBuf::from(DIRECTORY)).context("could not read directory")?;
+        let entries = match fs::read_dir(PathBuf::from(DIRECTORY)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).context("could not read directory"),
+        };
 
         for entry in entries {
             let entry = entry.context("could not read directory entry")?;
@@ -103,31 +202,583 @@ impl Video for VideoImpl {
 }
 
 impl VideoImpl {
-    fn write(&self, path: &PathBuf, body: &Vec<u8>) -> Result<()> {
-        let mut file = fs::File::create(path).context("could not create file")?;
+    // extracts the first frame, fit to the profile the same way images are
+    async fn poster(&self, input: &Input, data: &[u8], profile: &Profile) -> Result<Formatted> {
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(input.args())
+            .arg("-hide_banner")
+            .arg("-loglevel")
+            .arg("error")
+            .arg("-an") // no audio
+            .arg("-frames:v") // single frame
+            .arg("1")
+            .arg("-vf") // video filter
+            .arg(self.scale(profile))
+            .arg("-c:v") // codec
+            .arg("libwebp")
+            .arg("-quality") // quality
+            .arg(profile.quality().to_string())
+            .arg("-f")
+            .arg("webp")
+            .arg("pipe:1");
 
-        file.write_all(body).context("could not write file")?;
+        let buffer = self
+            .run(&mut command, input.stdin(data), MAX_POSTER_SECONDS)
+            .await
+            .context("poster process failed")?;
 
-        Ok(())
+        Ok((buffer, Format::WebP))
     }
 
-    fn read(&self, path: &PathBuf) -> Result<Vec<u8>> {
-        let mut file = fs::File::open(path).context("could not open file")?;
+    async fn probe(&self, input: &Input, data: &[u8]) -> Result<Probe> {
+        let mut command = Command::new("ffprobe");
+        command
+            .arg("-v")
+            .arg("error")
+            .arg("-select_streams") // first video stream
+            .arg("v:0")
+            .arg("-count_packets") // demux only, much cheaper than decoding frames
+            .arg("-show_entries")
+            .arg("stream=width,height,avg_frame_rate,nb_read_packets:format=duration")
+            .arg("-of")
+            .arg("json")
+            .args(input.args());
 
-        let mut buffer = Vec::new();
+        let stdout = self
+            .run(&mut command, input.stdin(data), MAX_PROBE_SECONDS)
+            .await
+            .context("probe process failed")?;
 
-        file.read_to_end(&mut buffer)
-            .context("could not read file")?;
+        serde_json::from_slice::<Probe>(&stdout)
+            .map_err(|e| invalid_media(format!("could not parse probe: {}", e)).into())
+    }
+
+    // the process is killed when the timeout drops it, so it never outlives the caller
+    async fn run(
+        &self,
+        command: &mut Command,
+        stdin: Option<Vec<&[u8]>>,
+        seconds: u64,
+    ) -> Result<Vec<u8>> {
+        let mut child = command
+            .kill_on_drop(true)
+            .stdin(match stdin {
+                Some(_) => Stdio::piped(),
+                None => Stdio::null(),
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("could not spawn process")?;
+
+        let pipe = child.stdin.take();
+
+        // stdin is written while stdout is drained, otherwise both sides can fill
+        // their pipe buffers and block on each other
+        let process = async {
+            let write = async {
+                if let (Some(mut pipe), Some(stdin)) = (pipe, stdin) {
+                    for chunk in stdin {
+                        match pipe.write_all(chunk).await {
+                            Ok(()) => {}
+                            // ffmpeg closes its end early once it has what it needs, e.g. a
+                            // single poster frame, so a broken pipe is expected here
+                            Err(e) if e.kind() == ErrorKind::BrokenPipe => break,
+                            Err(e) => return Err(e).context("could not write to process"),
+                        }
+                    }
+                }
+
+                Ok(())
+            };
+
+            let (written, output) = tokio::join!(write, child.wait_with_output());
+            written?;
 
-        Ok(buffer)
+            output.context("could not run process")
+        };
+
+        let output = match timeout(Duration::from_secs(seconds), process).await {
+            Ok(output) => output?,
+            // the same media would run out of time again, so it is not worth a retry
+            Err(_) => {
+                return Err(media_too_large(format!("processing took over {}s", seconds)).into())
+            }
+        };
+
+        if !output.status.success() {
+            return Err(invalid_media(format!(
+                "process exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+            .into());
+        }
+
+        Ok(output.stdout)
     }
 
-    fn get_path(&self, file_name: String, format: Format) -> PathBuf {
-        let mut path = PathBuf::from(DIRECTORY);
+    fn scale(&self, profile: &Profile) -> String {
+        let (width, height) = (profile.width(), profile.height());
+
+        match profile.fit() {
+            Fit::Contain => format!(
+                "scale={}:{}:force_original_aspect_ratio=decrease",
+                width, height
+            ),
+            Fit::Cover => format!(
+                "scale={0}:{1}:force_original_aspect_ratio=increase,crop={0}:{1}",
+                width, height
+            ),
+            Fit::Fill => format!("scale={}:{}", width, height),
+        }
+    }
+}
 
-        path.push(file_name);
+// where ffmpeg reads its input from
+enum Input {
+    Pipe,
+    File(TempFile),
+    // decoded frames as a png sequence, for inputs ffmpeg can't demux itself
+    Frames {
+        sequence: animation::Sequence,
+        fps: u32,
+    },
+}
+
+impl Input {
+    // mp4 may keep its index at the end of the file, which can only be reached by seeking
+    async fn new(
+        data: &[u8],
+        format: Format,
+        fps: u32,
+        seconds: u32,
+        size: (u32, u32),
+        pool: &Pool,
+    ) -> Result<Input> {
+        match format {
+            Format::Mp4 => Ok(Input::File(TempFile::new(data, format).await?)),
+            Format::WebP => {
+                let data = data.to_vec();
+                let sequence = pool
+                    .run_blocking(move || animation::png_sequence(&data, fps, seconds, size))
+                    .await?;
+
+                Ok(Input::Frames { sequence, fps })
+            }
+            _ => Ok(Input::Pipe),
+        }
+    }
+
+    // input options and the input itself, ffmpeg and ffprobe both take them as is
+    fn args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+
+        if let Input::Frames { fps, .. } = self {
+            args.extend(["-f", "png_pipe", "-framerate"].map(OsString::from));
+            args.push(OsString::from(fps.to_string()));
+        }
+
+        args.push(OsString::from("-i"));
+        args.push(match self {
+            Input::File(file) => file.path.clone().into_os_string(),
+            _ => OsString::from("pipe:0"),
+        });
+
+        args
+    }
+
+    // written in order, so repeated frames don't need copying into one buffer
+    fn stdin<'a>(&'a self, data: &'a [u8]) -> Option<Vec<&'a [u8]>> {
+        match self {
+            Input::Pipe => Some(vec![data]),
+            Input::File(_) => None,
+            Input::Frames { sequence, .. } => Some(sequence.samples()),
+        }
+    }
+}
+
+// removed on drop, so failed and cancelled transcodes don't leak disk
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    async fn new(data: &[u8], format: Format) -> Result<TempFile> {
+        tokio::fs::create_dir_all(DIRECTORY)
+            .await
+            .context("could not create directory")?;
+
+        let mut path = PathBuf::from(DIRECTORY);
+        path.push(uuid::Uuid::new_v4().to_string());
         path.set_extension(format.extension());
 
-        path
+        // owned before writing so a failed write is still cleaned up
+        let file = TempFile { path };
+
+        tokio::fs::write(&file.path, data)
+            .await
+            .context("could not write file")?;
+
+        Ok(file)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            tracing::warn!("could not remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Probe {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: Option<ProbeFormat>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProbeStream {
+    width: Option<u32>,
+    height: Option<u32>,
+    // ffprobe reports counts, rates and durations as strings
+    nb_read_packets: Option<String>,
+    // a fraction such as 30000/1001, 0/0 when unknown
+    avg_frame_rate: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProbeFormat {
+    duration: Option<String>,
+}
+
+impl Probe {
+    fn check(&self) -> Result<()> {
+        let stream = match self.streams.first() {
+            Some(stream) => stream,
+            None => return Err(invalid_media(String::from("no video stream")).into()),
+        };
+
+        let (width, height) = match (stream.width, stream.height) {
+            (Some(width), Some(height)) if width > 0 && height > 0 => (width, height),
+            _ => return Err(invalid_media(String::from("unknown dimensions")).into()),
+        };
+
+        if width > MAX_INPUT_DIMENSION || height > MAX_INPUT_DIMENSION {
+            return Err(media_too_large(format!("dimensions {}x{}", width, height)).into());
+        }
+
+        // an unknown count would skip the limit, so it is refused rather than assumed small
+        let frames = match self.frames() {
+            Some(frames) => frames,
+            None => return Err(invalid_media(String::from("unknown frame count")).into()),
+        };
+
+        if frames > MAX_INPUT_FRAMES {
+            return Err(media_too_large(format!("{} frames", frames)).into());
+        }
+
+        if width as u64 * height as u64 * frames > MAX_INPUT_PIXELS {
+            return Err(
+                media_too_large(format!("{} frames of {}x{}", frames, width, height)).into(),
+            );
+        }
+
+        let duration = match self.total_duration() {
+            Some(duration) => duration,
+            None => return Err(invalid_media(String::from("unknown duration")).into()),
+        };
+
+        if duration > MAX_INPUT_DURATION_SECONDS {
+            return Err(media_too_large(format!("duration {}s", duration)).into());
+        }
+
+        Ok(())
+    }
+
+    fn attributes(&self) -> Attributes {
+        let mut attributes = attributes::new();
+
+        let stream = match self.streams.first() {
+            Some(stream) => stream,
+            None => return attributes,
+        };
+
+        if let (Some(width), Some(height)) = (stream.width, stream.height) {
+            attributes = attributes.with_dimensions(width, height);
+        }
+
+        if let Some(frame_rate) = self.frame_rate() {
+            attributes = attributes.with_frame_rate(frame_rate);
+        }
+
+        if let Some(duration) = self.total_duration() {
+            attributes = attributes.with_duration(duration);
+        }
+
+        attributes
+    }
+
+    // fragmented mp4 and piped frames have no overall duration, but their frames add up
+    // to one
+    fn total_duration(&self) -> Option<f64> {
+        self.duration().or_else(|| {
+            self.frames()
+                .zip(self.frame_rate())
+                .map(|(frames, frame_rate)| frames as f64 / frame_rate)
+        })
+    }
+
+    fn frames(&self) -> Option<u64> {
+        self.streams
+            .first()
+            .and_then(|stream| stream.nb_read_packets.as_deref())
+            .and_then(|frames| frames.parse::<u64>().ok())
+    }
+
+    fn duration(&self) -> Option<f64> {
+        self.format
+            .as_ref()
+            .and_then(|format| format.duration.as_deref())
+            .and_then(|duration| duration.parse::<f64>().ok())
+    }
+
+    fn frame_rate(&self) -> Option<f64> {
+        let (numerator, denominator) = self
+            .streams
+            .first()
+            .and_then(|stream| stream.avg_frame_rate.as_deref())
+            .and_then(|rate| rate.split_once('/'))?;
+
+        let numerator = numerator.parse::<f64>().ok()?;
+        let denominator = denominator.parse::<f64>().ok()?;
+
+        match numerator > 0.0 && denominator > 0.0 {
+            true => Some(numerator / denominator),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::{
+            errors::{self, InvalidMedia, MediaTooLarge},
+            profile,
+        },
+        gateways::pool,
+    };
+
+    fn video() -> VideoImpl {
+        VideoImpl {
+            pool: Arc::new(pool::new(1)),
+            image_pool: Arc::new(pool::new(1)),
+        }
+    }
+
+    fn profile(fit: &str) -> Profile {
+        serde_json::from_value::<Profile>(serde_json::json!({
+            "name": "test",
+            "width": 300,
+            "height": 200,
+            "fit": fit,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn scales_to_the_profile_box() {
+        let video = video();
+
+        assert_eq!(
+            video.scale(&profile("contain")),
+            "scale=300:200:force_original_aspect_ratio=decrease"
+        );
+        assert_eq!(
+            video.scale(&profile("cover")),
+            "scale=300:200:force_original_aspect_ratio=increase,crop=300:200"
+        );
+        assert_eq!(video.scale(&profile("fill")), "scale=300:200");
+    }
+
+    fn probe(json: serde_json::Value) -> Result<()> {
+        serde_json::from_value::<Probe>(json).unwrap().check()
+    }
+
+    #[test]
+    fn accepts_media_within_limits() {
+        let result = probe(serde_json::json!({
+            "streams": [{ "width": 480, "height": 270, "nb_read_packets": "120" }],
+            "format": { "duration": "7.5" },
+        }));
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn rejects_media_over_limits() {
+        let inputs = [
+            serde_json::json!({ "streams": [{ "width": 8000, "height": 10 }] }),
+            serde_json::json!({
+                "streams": [{ "width": 10, "height": 10, "nb_read_packets": "100000" }],
+            }),
+            serde_json::json!({
+                "streams": [{ "width": 10, "height": 10, "nb_read_packets": "10" }],
+                "format": { "duration": "3600.0" },
+            }),
+            serde_json::json!({
+                "streams": [{ "width": 4096, "height": 4096, "nb_read_packets": "3000" }],
+                "format": { "duration": "100.0" },
+            }),
+        ];
+
+        for input in inputs {
+            let e = probe(input).unwrap_err();
+            assert!(errors::find::<MediaTooLarge>(&e).is_some());
+        }
+    }
+
+    #[test]
+    fn describes_probed_media() {
+        let probe = serde_json::from_value::<Probe>(serde_json::json!({
+            "streams": [{
+                "width": 480,
+                "height": 270,
+                "nb_read_packets": "120",
+                "avg_frame_rate": "16/1",
+            }],
+            "format": {},
+        }))
+        .unwrap();
+
+        assert_eq!(
+            probe.attributes(),
+            attributes::new()
+                .with_dimensions(480, 270)
+                .with_frame_rate(16.0)
+                .with_duration(7.5)
+        );
+    }
+
+    #[test]
+    fn rejects_media_without_frame_count_or_duration() {
+        let inputs = [
+            serde_json::json!({
+                "streams": [{ "width": 10, "height": 10 }],
+                "format": { "duration": "1.0" },
+            }),
+            serde_json::json!({
+                "streams": [{ "width": 10, "height": 10, "nb_read_packets": "10" }],
+                "format": {},
+            }),
+        ];
+
+        for input in inputs {
+            let e = probe(input).unwrap_err();
+            assert!(errors::find::<InvalidMedia>(&e).is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn ignores_processes_closing_stdin_early() {
+        let video = video();
+        let input = vec![7; 4 * 1024 * 1024];
+
+        let output = video
+            .run(
+                Command::new("head").args(["-c", "1"]),
+                Some(vec![&input[..]]),
+                5,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(output, vec![7]);
+    }
+
+    #[tokio::test]
+    async fn reports_timeouts_as_too_large() {
+        let video = video();
+
+        let e = video
+            .run(Command::new("sleep").arg("5"), None, 1)
+            .await
+            .unwrap_err();
+
+        assert!(errors::find::<MediaTooLarge>(&e).is_some());
+    }
+
+    #[test]
+    fn rejects_media_without_video() {
+        let e = probe(serde_json::json!({ "streams": [] })).unwrap_err();
+
+        assert!(errors::find::<InvalidMedia>(&e).is_some());
+    }
+
+    #[tokio::test]
+    async fn temp_file_is_removed_on_drop() {
+        let file = TempFile::new(b"video", Format::Mp4).await.unwrap();
+        let path = file.path.clone();
+        assert_eq!(fs::read(&path).unwrap(), b"video");
+
+        drop(file);
+
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn pipes_inputs_that_do_not_need_seeking() {
+        let gif = Input::new(b"gif", Format::Gif, 16, 30, (300, 200), &pool::new(1))
+            .await
+            .unwrap();
+        assert_eq!(gif.stdin(b"gif"), Some(vec![&b"gif"[..]]));
+        assert_eq!(gif.args(), ["-i", "pipe:0"]);
+
+        let mp4 = Input::new(b"mp4", Format::Mp4, 16, 30, (300, 200), &pool::new(1))
+            .await
+            .unwrap();
+        assert_eq!(mp4.stdin(b"mp4"), None);
+        assert!(PathBuf::from(&mp4.args()[1]).exists());
+    }
+
+    #[tokio::test]
+    async fn pipes_animated_webp_as_png_sequence() {
+        let data = animation::tests::animated_webp();
+
+        let webp = Input::new(&data, Format::WebP, 4, 30, (300, 200), &pool::new(1))
+            .await
+            .unwrap();
+
+        assert!(webp.stdin(&data).unwrap()[0].starts_with(b"\x89PNG"));
+        assert_eq!(
+            webp.args(),
+            ["-f", "png_pipe", "-framerate", "4", "-i", "pipe:0"]
+        );
+    }
+
+    #[test]
+    fn video_options_default_and_validate() {
+        let options = profile("contain").video();
+        assert_eq!(
+            (options.fps(), options.crf(), options.max_duration()),
+            (16, 23, 30)
+        );
+
+        let invalid = profile::new("test", 10, 10).with_video(profile::video_options(0, 23, 10));
+        assert!(invalid.validate().is_err());
+
+        let gif = serde_json::from_value::<Profile>(serde_json::json!({
+            "name": "test",
+            "width": 10,
+            "height": 10,
+            "video": { "format": "gif" },
+        }))
+        .unwrap();
+        assert!(gif.validate().is_err());
     }
 }
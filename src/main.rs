@@ -11,12 +11,14 @@ mod usecases;
 
 #[tokio::main]
 async fn main() {
-    let container = Arc::new(container::new().await);
+    let container = Arc::new(container::new().await.expect("could not start"));
 
-    let cloned_container = container.clone();
-    tokio::spawn(async move {
-        controllers::cleaner::start(cloned_container).await;
-    });
+    if container.settings.clean_videos() {
+        let cloned_container = container.clone();
+        tokio::spawn(async move {
+            controllers::cleaner::start(cloned_container).await;
+        });
+    }
 
     http::start(container.clone()).await;
 }
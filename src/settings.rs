@@ -1,7 +1,14 @@
+use crate::common::{
+    profile::{self, Profile},
+    variant::{self, Variant},
+};
 use dotenv::dotenv;
 use std::env;
 use tracing_subscriber::fmt;
 
+pub const THUMBNAIL: &str = "thumbnail";
+pub const AVATAR: &str = "avatar";
+
 pub struct Settings {
     env: String,
     api_key: String,
@@ -9,7 +16,21 @@ pub struct Settings {
     bucket: String,
     endpoint: String,
     storage_external_url: String,
+    storage_backend: String,
+    storage_root: String,
     ipfs_gateway_url: String,
+    profiles: Vec<Profile>,
+    avif_speed: u8,
+    clean_videos: bool,
+    max_image_width: u32,
+    max_image_height: u32,
+    max_image_pixels: u64,
+    image_concurrency: usize,
+    video_concurrency: usize,
+    video_queue: usize,
+    strip_metadata: bool,
+    content_addressed: bool,
+    index_path: String,
 }
 
 pub fn new() -> Settings {
@@ -22,9 +43,69 @@ pub fn new() -> Settings {
         bucket: env::var("BUCKET").unwrap(),
         endpoint: env::var("ENDPOINT").unwrap(),
         storage_external_url: env::var("STORAGE_EXTERNAL_URL").unwrap(),
+        storage_backend: env::var("STORAGE_BACKEND").unwrap_or(String::from("s3")),
+        storage_root: env::var("STORAGE_ROOT").unwrap_or(String::from("/tmp/daochan/storage")),
         ipfs_gateway_url: env::var("IPFS_GATEWAY_URL").unwrap(),
+        profiles: match env::var("VARIANTS") {
+            Ok(variants) => serde_json::from_str(&variants).unwrap(),
+            Err(_) => default_profiles(),
+        },
+        avif_speed: env::var("AVIF_SPEED")
+            .map(|speed| speed.parse().unwrap())
+            .unwrap_or(6),
+        clean_videos: env::var("CLEAN_VIDEOS")
+            .map(|clean| clean.parse().unwrap())
+            .unwrap_or(false),
+        max_image_width: env::var("MAX_IMAGE_WIDTH")
+            .map(|width| width.parse().unwrap())
+            .unwrap_or(8192),
+        max_image_height: env::var("MAX_IMAGE_HEIGHT")
+            .map(|height| height.parse().unwrap())
+            .unwrap_or(8192),
+        max_image_pixels: env::var("MAX_IMAGE_PIXELS")
+            .map(|pixels| pixels.parse().unwrap())
+            .unwrap_or(40_000_000),
+        image_concurrency: env::var("IMAGE_CONCURRENCY")
+            .map(|concurrency| concurrency.parse().unwrap())
+            .unwrap_or_else(|_| {
+                std::thread::available_parallelism()
+                    .map(|parallelism| parallelism.get())
+                    .unwrap_or(1)
+            }),
+        video_concurrency: env::var("VIDEO_CONCURRENCY")
+            .map(|concurrency| concurrency.parse().unwrap())
+            .unwrap_or(2),
+        video_queue: env::var("VIDEO_QUEUE")
+            .map(|queue| queue.parse().unwrap())
+            .unwrap_or(8),
+        strip_metadata: env::var("STRIP_METADATA")
+            .map(|strip| strip.parse().unwrap())
+            .unwrap_or(true),
+        content_addressed: env::var("CONTENT_ADDRESSED")
+            .map(|content_addressed| content_addressed.parse().unwrap())
+            .unwrap_or(false),
+        // outside of /tmp, the index can't be rebuilt without processing every image again.
+        // a relative path resolves against the working directory, /app in the image
+        index_path: env::var("INDEX_PATH").unwrap_or(String::from("data/index.jsonl")),
     };
 
+    validate_profiles(&settings.profiles);
+
+    if !(1..=10).contains(&settings.avif_speed) {
+        panic!("avif speed must be between 1 and 10");
+    }
+
+    if settings.max_image_width == 0
+        || settings.max_image_height == 0
+        || settings.max_image_pixels == 0
+    {
+        panic!("image limits must be non zero");
+    }
+
+    if settings.image_concurrency == 0 || settings.video_concurrency == 0 {
+        panic!("image and video concurrency must be non zero");
+    }
+
     let subscriber_builder = fmt().with_target(false);
 
     if settings.is_dev() {
@@ -39,7 +120,90 @@ pub fn new() -> Settings {
             .init();
     }
 
-    return settings;
+    settings
+}
+
+// settings for unit tests that don't touch the environment or install a subscriber
+#[cfg(test)]
+pub fn test() -> Settings {
+    Settings {
+        env: String::from("test"),
+        api_key: String::from("api-key"),
+        region: String::from("region"),
+        bucket: String::from("bucket"),
+        endpoint: String::from("http://localhost:9000"),
+        storage_external_url: String::from("http://localhost:9000/bucket"),
+        storage_backend: String::from("fs"),
+        storage_root: String::from("/tmp/daochan/storage"),
+        ipfs_gateway_url: String::from("https://ipfs.io"),
+        profiles: default_profiles(),
+        avif_speed: 10,
+        clean_videos: false,
+        max_image_width: 8192,
+        max_image_height: 8192,
+        max_image_pixels: 40_000_000,
+        image_concurrency: 1,
+        video_concurrency: 1,
+        video_queue: 1,
+        strip_metadata: true,
+        content_addressed: false,
+        index_path: String::from("/tmp/daochan/index.jsonl"),
+    }
+}
+
+#[cfg(test)]
+impl Settings {
+    pub fn with_ipfs_gateway_url(mut self, ipfs_gateway_url: String) -> Settings {
+        self.ipfs_gateway_url = ipfs_gateway_url;
+        self
+    }
+
+    pub fn with_index_path(mut self, index_path: String) -> Settings {
+        self.index_path = index_path;
+        self
+    }
+
+    pub fn with_content_addressed(mut self, content_addressed: bool) -> Settings {
+        self.content_addressed = content_addressed;
+        self
+    }
+}
+
+// avatars are small and looped so their videos are kept short and cheap
+fn default_profiles() -> Vec<Profile> {
+    vec![
+        profile::new(THUMBNAIL, 300, 300),
+        profile::new(AVATAR, 125, 125).with_video(profile::video_options(12, 28, 10)),
+    ]
+}
+
+// invalid variants are a deployment error so fail fast on startup
+fn validate_profiles(profiles: &[Profile]) {
+    for (i, profile) in profiles.iter().enumerate() {
+        let name = profile.name();
+
+        if name == Variant::Original.name()
+            || name == profile::TRANSFORM
+            || name.ends_with(variant::POSTER_SUFFIX)
+        {
+            panic!("variant name {} is reserved", name);
+        }
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            panic!("variant name {} must be alphanumeric", name);
+        }
+        if profiles[..i].iter().any(|p| p.name() == name) {
+            panic!("variant {} is defined more than once", name);
+        }
+        if let Err(e) = profile.validate() {
+            panic!("variant {} is invalid: {}", name, e);
+        }
+    }
+
+    for required in [THUMBNAIL, AVATAR] {
+        if !profiles.iter().any(|p| p.name() == required) {
+            panic!("variant {} is required", required);
+        }
+    }
 }
 
 impl Settings {
@@ -71,7 +235,91 @@ impl Settings {
         self.storage_external_url.clone()
     }
 
+    pub fn storage_backend(&self) -> String {
+        self.storage_backend.clone()
+    }
+
+    pub fn storage_root(&self) -> String {
+        self.storage_root.clone()
+    }
+
     pub fn ipfs_gateway_url(&self) -> String {
         self.ipfs_gateway_url.clone()
     }
+
+    // 1 (slowest, smallest) to 10 (fastest, largest)
+    pub fn avif_speed(&self) -> u8 {
+        self.avif_speed
+    }
+
+    // video temp files remove themselves, the sweep only matters after crashes
+    pub fn clean_videos(&self) -> bool {
+        self.clean_videos
+    }
+
+    pub fn max_image_width(&self) -> u32 {
+        self.max_image_width
+    }
+
+    pub fn max_image_height(&self) -> u32 {
+        self.max_image_height
+    }
+
+    pub fn max_image_pixels(&self) -> u64 {
+        self.max_image_pixels
+    }
+
+    pub fn image_concurrency(&self) -> usize {
+        self.image_concurrency
+    }
+
+    pub fn video_concurrency(&self) -> usize {
+        self.video_concurrency
+    }
+
+    // transcodes allowed to wait for a slot before uploads are turned away
+    pub fn video_queue(&self) -> usize {
+        self.video_queue
+    }
+
+    // keeps gps coordinates, camera serials and the like out of stored originals
+    pub fn strip_metadata(&self) -> bool {
+        self.strip_metadata
+    }
+
+    // name uploads after their content so the same bytes are only stored once
+    pub fn content_addressed(&self) -> bool {
+        self.content_addressed
+    }
+
+    // where fingerprints for finding similar images are kept
+    pub fn index_path(&self) -> String {
+        self.index_path.clone()
+    }
+
+    // every configured variant, excluding the original
+    pub fn variants(&self) -> Vec<Variant> {
+        self.profiles
+            .iter()
+            .map(|profile| Variant::Profile(profile.clone()))
+            .collect()
+    }
+
+    pub fn variant(&self, name: &str) -> Option<Variant> {
+        self.variants().into_iter().find(|v| v.name() == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "variant name thumbnail_poster is reserved")]
+    fn rejects_names_that_clash_with_posters() {
+        let mut profiles = default_profiles();
+        profiles.push(profile::new("thumbnail_poster", 100, 100));
+
+        validate_profiles(&profiles);
+    }
 }
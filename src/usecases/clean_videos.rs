@@ -18,13 +18,15 @@ impl CleanVideos {
     pub async fn execute(&self) -> Result<()> {
         tracing::info!("cleaning videos");
 
-        self.video
+        let result = self
+            .video
             .clean(STALE_SECONDS)
             .await
-            .context("could not clean videos")?;
+            .context("could not clean videos");
 
+        // sleep even on failure so a persistent error doesn't spin the loop
         tokio::time::sleep(tokio::time::Duration::from_secs(STALE_SECONDS)).await;
 
-        Ok(())
+        result
     }
 }
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use crate::common::variant::Variant;
+
+use super::gateways::{Index, Storage};
+
+pub struct DeleteImage {
+    storage: Arc<dyn Storage>,
+    index: Arc<dyn Index>,
+    variants: Vec<Variant>,
+}
+
+pub fn new(
+    storage: Arc<dyn Storage>,
+    index: Arc<dyn Index>,
+    variants: Vec<Variant>,
+) -> DeleteImage {
+    DeleteImage {
+        storage,
+        index,
+        variants,
+    }
+}
+
+impl DeleteImage {
+    pub async fn execute(&self, file_name: String) -> Result<Vec<String>> {
+        let mut deleted = self
+            .storage
+            .delete_transforms(file_name.clone())
+            .await
+            .context("could not delete transforms")?;
+
+        // derived variants are removed before the original so a failure part way
+        // through never leaves variants behind that can no longer be regenerated
+        let variants = self
+            .variants
+            .iter()
+            .filter_map(Variant::poster)
+            .chain(self.variants.iter().cloned())
+            .chain([Variant::Original]);
+
+        for variant in variants {
+            if let Some(key) = self
+                .storage
+                .delete(variant.clone(), file_name.clone())
+                .await
+                .with_context(|| format!("could not delete {}", variant.name()))?
+            {
+                deleted.push(key);
+            }
+        }
+
+        // a stale fingerprint only costs a lookup, similar images skips missing ones
+        if let Err(e) = self.index.remove(file_name).await {
+            tracing::warn!("could not remove image from index: {}", e);
+        }
+
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::{
+            format::Format,
+            profile::{self, Fit},
+        },
+        usecases::{
+            fakes::{avatar, thumbnail, FakeIndex, FakeStorage},
+            gateways::Index,
+        },
+    };
+
+    #[tokio::test]
+    async fn deletes_every_variant() {
+        let storage = FakeStorage::new();
+        storage.insert(Variant::Original, "a", "image/png", b"original");
+        storage.insert(thumbnail(), "a", "image/webp", b"thumbnail");
+        storage.insert(avatar(), "b", "image/webp", b"avatar");
+        let index = FakeIndex::new();
+        index.insert(String::from("a"), 1).await.unwrap();
+        let delete_image = new(storage.clone(), index.clone(), vec![thumbnail(), avatar()]);
+
+        let deleted = delete_image.execute(String::from("a")).await.unwrap();
+
+        assert_eq!(
+            deleted,
+            vec![thumbnail().key("a"), Variant::Original.key("a"),]
+        );
+        assert_eq!(storage.object(Variant::Original, "a"), None);
+        assert_eq!(storage.object(thumbnail(), "a"), None);
+        assert!(storage.object(avatar(), "b").is_some());
+        assert_eq!(index.fingerprint("a"), None);
+    }
+
+    #[tokio::test]
+    async fn deletes_cached_transforms() {
+        let storage = FakeStorage::new();
+        let transform =
+            Variant::Transform(profile::transform(10, 10, Fit::Cover, Format::WebP, 80));
+        storage.insert(Variant::Original, "a", "image/png", b"original");
+        storage.insert(transform.clone(), "a", "image/webp", b"transform");
+        let delete_image = new(
+            storage.clone(),
+            FakeIndex::new(),
+            vec![thumbnail(), avatar()],
+        );
+
+        let deleted = delete_image.execute(String::from("a")).await.unwrap();
+
+        assert_eq!(
+            deleted,
+            vec![transform.key("a"), Variant::Original.key("a"),]
+        );
+        assert_eq!(storage.object(transform, "a"), None);
+    }
+
+    #[tokio::test]
+    async fn deletes_posters() {
+        let storage = FakeStorage::new();
+        let poster = thumbnail().poster().unwrap();
+        storage.insert(Variant::Original, "a", "image/gif", b"original");
+        storage.insert(thumbnail(), "a", "video/mp4", b"thumbnail");
+        storage.insert(poster.clone(), "a", "image/webp", b"poster");
+        let delete_image = new(
+            storage.clone(),
+            FakeIndex::new(),
+            vec![thumbnail(), avatar()],
+        );
+
+        let deleted = delete_image.execute(String::from("a")).await.unwrap();
+
+        assert_eq!(deleted[0], poster.key("a"));
+        assert_eq!(storage.object(poster, "a"), None);
+    }
+
+    #[tokio::test]
+    async fn returns_nothing_when_image_does_not_exist() {
+        let storage = FakeStorage::new();
+        let delete_image = new(
+            storage.clone(),
+            FakeIndex::new(),
+            vec![thumbnail(), avatar()],
+        );
+
+        let deleted = delete_image.execute(String::from("a")).await.unwrap();
+
+        assert!(deleted.is_empty());
+        assert_eq!(storage.deletes().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn keeps_original_when_variant_delete_fails() {
+        let storage = FakeStorage::new();
+        storage.insert(Variant::Original, "a", "image/png", b"original");
+        storage.fail_delete(avatar());
+        let delete_image = new(
+            storage.clone(),
+            FakeIndex::new(),
+            vec![thumbnail(), avatar()],
+        );
+
+        let result = delete_image.execute(String::from("a")).await;
+
+        assert!(result.is_err());
+        assert!(storage.object(Variant::Original, "a").is_some());
+    }
+}
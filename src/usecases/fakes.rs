@@ -0,0 +1,474 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+
+use crate::common::{
+    appearance::{self, Appearance},
+    attributes::{self, Attributes},
+    format::Format,
+    profile,
+    variant::Variant,
+};
+
+use super::gateways::{Formatted, Images, Index, Storage, Video, Web};
+
+// PNG magic bytes are enough for Format::infer to recognise the data as an image
+pub const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+pub const GIF: &[u8] = b"GIF89a\x01\0\x01\0\0\0\0";
+// a vp8x header with the animation flag set
+pub const ANIMATED_WEBP: &[u8] = b"RIFF\x24\0\0\0WEBPVP8X\x0a\0\0\0\x02\0\0\0\0\0\0\0\0\0";
+
+// content type and body
+type Object = (String, Vec<u8>);
+
+pub fn thumbnail() -> Variant {
+    Variant::Profile(profile::new("thumbnail", 300, 300))
+}
+
+pub fn avatar() -> Variant {
+    Variant::Profile(profile::new("avatar", 125, 125))
+}
+
+#[derive(Default)]
+pub struct FakeStorage {
+    objects: Mutex<HashMap<(Variant, String), Object>>,
+    attributes: Mutex<HashMap<(Variant, String), Attributes>>,
+    uploads: Mutex<Vec<(Variant, String)>>,
+    gets: Mutex<Vec<(Variant, String)>>,
+    deletes: Mutex<Vec<(Variant, String)>>,
+    failing_uploads: Mutex<Vec<Variant>>,
+    failing_gets: Mutex<Vec<Variant>>,
+    failing_deletes: Mutex<Vec<Variant>>,
+}
+
+impl FakeStorage {
+    pub fn new() -> Arc<FakeStorage> {
+        Arc::new(FakeStorage::default())
+    }
+
+    pub fn insert(&self, variant: Variant, file_name: &str, content_type: &str, body: &[u8]) {
+        self.objects.lock().unwrap().insert(
+            (variant, file_name.to_string()),
+            (content_type.to_string(), body.to_vec()),
+        );
+    }
+
+    pub fn object(&self, variant: Variant, file_name: &str) -> Option<Object> {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(&(variant, file_name.to_string()))
+            .cloned()
+    }
+
+    pub fn attributes(&self, variant: Variant, file_name: &str) -> Option<Attributes> {
+        self.attributes
+            .lock()
+            .unwrap()
+            .get(&(variant, file_name.to_string()))
+            .cloned()
+    }
+
+    pub fn insert_attributes(&self, variant: Variant, file_name: &str, attributes: Attributes) {
+        self.attributes
+            .lock()
+            .unwrap()
+            .insert((variant, file_name.to_string()), attributes);
+    }
+
+    pub fn fail_upload(&self, variant: Variant) {
+        self.failing_uploads.lock().unwrap().push(variant);
+    }
+
+    pub fn fail_get(&self, variant: Variant) {
+        self.failing_gets.lock().unwrap().push(variant);
+    }
+
+    pub fn fail_delete(&self, variant: Variant) {
+        self.failing_deletes.lock().unwrap().push(variant);
+    }
+
+    pub fn uploads(&self) -> Vec<(Variant, String)> {
+        self.uploads.lock().unwrap().clone()
+    }
+
+    pub fn gets(&self) -> Vec<(Variant, String)> {
+        self.gets.lock().unwrap().clone()
+    }
+
+    pub fn deletes(&self) -> Vec<(Variant, String)> {
+        self.deletes.lock().unwrap().clone()
+    }
+
+    pub fn url(variant: &Variant, file_name: &str) -> String {
+        format!("memory://{}", variant.key(file_name))
+    }
+}
+
+#[async_trait]
+impl Storage for FakeStorage {
+    async fn upload(
+        &self,
+        file_name: String,
+        variant: Variant,
+        content_type: String,
+        body: Vec<u8>,
+        attributes: Attributes,
+    ) -> Result<String> {
+        self.uploads
+            .lock()
+            .unwrap()
+            .push((variant.clone(), file_name.clone()));
+
+        if self.failing_uploads.lock().unwrap().contains(&variant) {
+            bail!("injected upload failure for {:?}", variant);
+        }
+
+        self.insert(variant.clone(), &file_name, &content_type, &body);
+        self.insert_attributes(variant.clone(), &file_name, attributes);
+
+        Ok(FakeStorage::url(&variant, &file_name))
+    }
+
+    async fn get(
+        &self,
+        variant: Variant,
+        file_name: String,
+    ) -> Result<Option<(String, String, Attributes)>> {
+        self.gets
+            .lock()
+            .unwrap()
+            .push((variant.clone(), file_name.clone()));
+
+        if self.failing_gets.lock().unwrap().contains(&variant) {
+            bail!("injected get failure for {:?}", variant);
+        }
+
+        let attributes = self
+            .attributes(variant.clone(), &file_name)
+            .unwrap_or_default();
+
+        Ok(self
+            .object(variant.clone(), &file_name)
+            .map(|(content_type, _)| {
+                (
+                    FakeStorage::url(&variant, &file_name),
+                    content_type,
+                    attributes,
+                )
+            }))
+    }
+
+    async fn delete(&self, variant: Variant, file_name: String) -> Result<Option<String>> {
+        self.deletes
+            .lock()
+            .unwrap()
+            .push((variant.clone(), file_name.clone()));
+
+        if self.failing_deletes.lock().unwrap().contains(&variant) {
+            bail!("injected delete failure for {:?}", variant);
+        }
+
+        Ok(self
+            .objects
+            .lock()
+            .unwrap()
+            .remove(&(variant.clone(), file_name.clone()))
+            .map(|_| variant.key(&file_name)))
+    }
+
+    async fn download(
+        &self,
+        variant: Variant,
+        file_name: String,
+    ) -> Result<Option<(Vec<u8>, String)>> {
+        if self.failing_gets.lock().unwrap().contains(&variant) {
+            bail!("injected download failure for {:?}", variant);
+        }
+
+        Ok(self
+            .object(variant, &file_name)
+            .map(|(content_type, body)| (body, content_type)))
+    }
+
+    async fn delete_transforms(&self, file_name: String) -> Result<Vec<String>> {
+        let mut objects = self.objects.lock().unwrap();
+
+        let transforms = objects
+            .keys()
+            .filter(|(variant, name)| {
+                matches!(variant, Variant::Transform(_)) && *name == file_name
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        Ok(transforms
+            .into_iter()
+            .map(|(variant, name)| {
+                objects.remove(&(variant.clone(), name.clone()));
+                variant.key(&name)
+            })
+            .collect())
+    }
+}
+
+#[derive(Default)]
+pub struct FakeImages {
+    calls: Mutex<Vec<(Variant, Format)>>,
+    failing: Mutex<bool>,
+}
+
+impl FakeImages {
+    pub fn new() -> Arc<FakeImages> {
+        Arc::new(FakeImages::default())
+    }
+
+    pub fn fail(&self) {
+        *self.failing.lock().unwrap() = true;
+    }
+
+    pub fn calls(&self) -> Vec<(Variant, Format)> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Images for FakeImages {
+    async fn format(
+        &self,
+        _data: &[u8],
+        variant: Variant,
+        input_format: Format,
+    ) -> Result<(Vec<u8>, Format)> {
+        self.calls.lock().unwrap().push((variant, input_format));
+
+        if *self.failing.lock().unwrap() {
+            bail!("injected images failure");
+        }
+
+        Ok((b"formatted image".to_vec(), Format::WebP))
+    }
+
+    async fn sanitize(&self, data: &[u8], input_format: Format) -> Result<Vec<u8>> {
+        match input_format {
+            Format::Svg => Ok(b"sanitized svg".to_vec()),
+            _ => Ok(data.to_vec()),
+        }
+    }
+
+    // the fingerprint is the length of the data, so tests can tell what was fingerprinted
+    async fn appearance(&self, data: &[u8], _input_format: Format) -> Result<Appearance> {
+        Ok(appearance::new(
+            format!("blurhash of {}", String::from_utf8_lossy(data)),
+            vec![String::from("#ff0000"), String::from("#0000ff")],
+            data.len() as u64,
+        ))
+    }
+
+    async fn describe(&self, _data: &[u8], _input_format: Format) -> Result<Attributes> {
+        Ok(attributes::new().with_dimensions(100, 50))
+    }
+}
+
+#[derive(Default)]
+pub struct FakeVideo {
+    calls: Mutex<Vec<(Variant, Format)>>,
+    failing: Mutex<bool>,
+    failing_posters: Mutex<bool>,
+}
+
+impl FakeVideo {
+    pub fn new() -> Arc<FakeVideo> {
+        Arc::new(FakeVideo::default())
+    }
+
+    pub fn fail(&self) {
+        *self.failing.lock().unwrap() = true;
+    }
+
+    pub fn fail_poster(&self) {
+        *self.failing_posters.lock().unwrap() = true;
+    }
+
+    pub fn calls(&self) -> Vec<(Variant, Format)> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Video for FakeVideo {
+    async fn format(
+        &self,
+        _data: &[u8],
+        variant: Variant,
+        input_format: Format,
+    ) -> Result<(Formatted, Option<(Variant, Formatted)>)> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push((variant.clone(), input_format.clone()));
+
+        if *self.failing.lock().unwrap() {
+            bail!("injected video failure");
+        }
+
+        let poster = match variant.poster() {
+            Some(poster_variant) => {
+                self.calls
+                    .lock()
+                    .unwrap()
+                    .push((poster_variant.clone(), input_format));
+
+                match *self.failing_posters.lock().unwrap() {
+                    true => None,
+                    false => Some((poster_variant, (b"poster".to_vec(), Format::WebP))),
+                }
+            }
+            None => None,
+        };
+
+        let output_format = match variant {
+            Variant::Profile(profile) => profile.video().format(),
+            _ => Format::Mp4,
+        };
+
+        Ok(((b"formatted video".to_vec(), output_format), poster))
+    }
+
+    async fn describe(&self, _data: &[u8], _input_format: Format) -> Result<Attributes> {
+        Ok(attributes::new()
+            .with_dimensions(300, 200)
+            .with_duration(2.0)
+            .with_frame_rate(16.0))
+    }
+
+    async fn clean(&self, _stale_seconds: u64) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct FakeWeb {
+    nft_image_urls: Mutex<HashMap<String, String>>,
+    image_data: Mutex<HashMap<String, Vec<u8>>>,
+    requests: Mutex<Vec<String>>,
+}
+
+impl FakeWeb {
+    pub fn new() -> Arc<FakeWeb> {
+        Arc::new(FakeWeb::default())
+    }
+
+    pub fn insert_nft(&self, url: &str, image_url: &str) {
+        self.nft_image_urls
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), image_url.to_string());
+    }
+
+    pub fn insert_image(&self, url: &str, data: &[u8]) {
+        self.image_data
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), data.to_vec());
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+// unknown urls fail the same way an unreachable host would
+#[async_trait]
+impl Web for FakeWeb {
+    async fn get_nft_image_url(&self, url: String) -> Result<String> {
+        self.requests.lock().unwrap().push(url.clone());
+
+        self.nft_image_urls
+            .lock()
+            .unwrap()
+            .get(&url)
+            .cloned()
+            .ok_or(anyhow!("no nft metadata at {}", url))
+    }
+
+    async fn get_image_data(&self, url: String) -> Result<Vec<u8>> {
+        self.requests.lock().unwrap().push(url.clone());
+
+        self.image_data
+            .lock()
+            .unwrap()
+            .get(&url)
+            .cloned()
+            .ok_or(anyhow!("no image at {}", url))
+    }
+}
+
+#[derive(Default)]
+pub struct FakeIndex {
+    fingerprints: Mutex<HashMap<String, u64>>,
+    failing: Mutex<bool>,
+}
+
+impl FakeIndex {
+    pub fn new() -> Arc<FakeIndex> {
+        Arc::new(FakeIndex::default())
+    }
+
+    pub fn fail(&self) {
+        *self.failing.lock().unwrap() = true;
+    }
+
+    pub fn fingerprint(&self, file_name: &str) -> Option<u64> {
+        self.fingerprints.lock().unwrap().get(file_name).copied()
+    }
+
+    fn check(&self) -> Result<()> {
+        if *self.failing.lock().unwrap() {
+            bail!("injected index failure");
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Index for FakeIndex {
+    async fn insert(&self, file_name: String, fingerprint: u64) -> Result<()> {
+        self.check()?;
+        self.fingerprints
+            .lock()
+            .unwrap()
+            .insert(file_name, fingerprint);
+        Ok(())
+    }
+
+    async fn get(&self, file_name: String) -> Result<Option<u64>> {
+        self.check()?;
+        Ok(self.fingerprint(&file_name))
+    }
+
+    async fn similar(&self, fingerprint: u64, distance: u32) -> Result<Vec<(String, u32)>> {
+        self.check()?;
+        let mut similar = self
+            .fingerprints
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(file_name, other)| (file_name.clone(), (fingerprint ^ other).count_ones()))
+            .filter(|(_, d)| *d <= distance)
+            .collect::<Vec<_>>();
+        similar.sort_by(|(a_name, a), (b_name, b)| a.cmp(b).then_with(|| a_name.cmp(b_name)));
+        Ok(similar)
+    }
+
+    async fn remove(&self, file_name: String) -> Result<()> {
+        self.check()?;
+        self.fingerprints.lock().unwrap().remove(&file_name);
+        Ok(())
+    }
+}
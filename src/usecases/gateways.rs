@@ -1,7 +1,12 @@
 use anyhow::Result;
 use async_trait::async_trait;
 
-use crate::common::{format::Format, variant::Variant};
+use crate::common::{
+    appearance::Appearance, attributes::Attributes, format::Format, variant::Variant,
+};
+
+// encoded bytes and the format they were encoded to
+pub type Formatted = (Vec<u8>, Format);
 
 #[async_trait]
 pub trait Storage: Send + Sync {
@@ -11,8 +16,21 @@ pub trait Storage: Send + Sync {
         variant: Variant,
         content_type: String,
         body: Vec<u8>,
+        attributes: Attributes,
     ) -> Result<String>;
-    async fn get(&self, variant: Variant, file_name: String) -> Result<Option<(String, String)>>;
+    // url, content type and the attributes stored with the object
+    async fn get(
+        &self,
+        variant: Variant,
+        file_name: String,
+    ) -> Result<Option<(String, String, Attributes)>>;
+    async fn delete(&self, variant: Variant, file_name: String) -> Result<Option<String>>;
+    async fn download(
+        &self,
+        variant: Variant,
+        file_name: String,
+    ) -> Result<Option<(Vec<u8>, String)>>;
+    async fn delete_transforms(&self, file_name: String) -> Result<Vec<String>>;
 }
 
 #[async_trait]
@@ -23,6 +41,22 @@ pub trait Images: Send + Sync {
         variant: Variant,
         input_format: Format,
     ) -> Result<(Vec<u8>, Format)>;
+    // returns the data that is safe to store and serve as the original
+    async fn sanitize(&self, data: &[u8], input_format: Format) -> Result<Vec<u8>>;
+    // the blurhash, palette and fingerprint, from one decode of the image
+    async fn appearance(&self, data: &[u8], input_format: Format) -> Result<Appearance>;
+    // dimensions as displayed, read without decoding where the format allows
+    async fn describe(&self, data: &[u8], input_format: Format) -> Result<Attributes>;
+}
+
+// fingerprints of uploaded images, for finding near duplicates
+#[async_trait]
+pub trait Index: Send + Sync {
+    async fn insert(&self, file_name: String, fingerprint: u64) -> Result<()>;
+    async fn get(&self, file_name: String) -> Result<Option<u64>>;
+    // file names within the hamming distance of the fingerprint, closest first
+    async fn similar(&self, fingerprint: u64, distance: u32) -> Result<Vec<(String, u32)>>;
+    async fn remove(&self, file_name: String) -> Result<()>;
 }
 
 #[async_trait]
@@ -33,11 +67,16 @@ pub trait Web: Send + Sync {
 
 #[async_trait]
 pub trait Video: Send + Sync {
+    // Transcodes the variant along with its poster, if it has one, in the same slot
+    // of the pool. The video is usable without a poster so failing to extract one
+    // only warns.
     async fn format(
         &self,
         data: &[u8],
         variant: Variant,
         input_format: Format,
-    ) -> Result<(Vec<u8>, Format)>;
+    ) -> Result<(Formatted, Option<(Variant, Formatted)>)>;
+    // dimensions, duration and frame rate of animations and video
+    async fn describe(&self, data: &[u8], input_format: Format) -> Result<Attributes>;
     async fn clean(&self, stale_seconds: u64) -> Result<()>;
 }
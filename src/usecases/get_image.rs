@@ -3,7 +3,7 @@ use std::sync::Arc;
 use anyhow::{bail, Result};
 
 use crate::{
-    common::variant::Variant,
+    common::{format::Format, variant::Variant},
     entities::image::{self, Image},
 };
 
@@ -21,20 +21,30 @@ impl GetImage {
     pub async fn execute(&self, file_name: String, variant: Variant) -> Result<Option<Image>> {
         let (original_result, thumbnail_result) = tokio::join!(
             self.storage.get(Variant::Original, file_name.to_string()),
-            self.storage.get(variant, file_name.to_string()),
+            self.storage.get(variant.clone(), file_name.to_string()),
         );
 
         match (original_result, thumbnail_result) {
             (
-                Ok(Some((original_url, original_content_type))),
-                Ok(Some((thumbnail_url, thumbnail_content_type))),
-            ) => Ok(Some(image::new(
-                file_name,
-                original_url,
-                original_content_type,
-                thumbnail_url,
-                thumbnail_content_type,
-            ))),
+                Ok(Some((original_url, original_content_type, original_attributes))),
+                Ok(Some((thumbnail_url, thumbnail_content_type, thumbnail_attributes))),
+            ) => {
+                let image = image::new(
+                    file_name.clone(),
+                    original_url,
+                    original_content_type,
+                    thumbnail_url,
+                    thumbnail_content_type.clone(),
+                )
+                .with_attributes(&original_attributes, &thumbnail_attributes);
+
+                // only transcoded variants have a poster
+                if thumbnail_content_type != Format::Mp4.content_type() {
+                    return Ok(Some(image));
+                }
+
+                self.with_poster(image, file_name, variant).await.map(Some)
+            }
             (Ok(Some(_)), Ok(None)) => {
                 tracing::warn!("original exists but thumbnail does not");
                 Ok(None)
@@ -51,4 +61,198 @@ impl GetImage {
             (_, Err(e)) => bail!("could not check if thumbnail exists: {}", e),
         }
     }
+
+    async fn with_poster(
+        &self,
+        image: Image,
+        file_name: String,
+        variant: Variant,
+    ) -> Result<Image> {
+        let poster_variant = match variant.poster() {
+            Some(poster_variant) => poster_variant,
+            None => return Ok(image),
+        };
+
+        match self.storage.get(poster_variant, file_name).await {
+            Ok(Some((poster_url, poster_content_type, poster_attributes))) => {
+                Ok(image.with_poster(poster_url, poster_content_type, &poster_attributes))
+            }
+            Ok(None) => Ok(image),
+            Err(e) => bail!("could not check if poster exists: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        common::attributes,
+        usecases::fakes::{thumbnail, FakeStorage},
+    };
+
+    fn setup() -> (Arc<FakeStorage>, GetImage) {
+        let storage = FakeStorage::new();
+        let get_image = new(storage.clone());
+        (storage, get_image)
+    }
+
+    #[tokio::test]
+    async fn returns_image_when_both_exist() {
+        let (storage, get_image) = setup();
+        storage.insert(Variant::Original, "a", "image/png", b"original");
+        storage.insert(thumbnail(), "a", "image/webp", b"thumbnail");
+
+        let image = get_image
+            .execute(String::from("a"), thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            image,
+            Some(image::new(
+                String::from("a"),
+                FakeStorage::url(&Variant::Original, "a"),
+                String::from("image/png"),
+                FakeStorage::url(&thumbnail(), "a"),
+                String::from("image/webp"),
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn returns_stored_attributes() {
+        let (storage, get_image) = setup();
+        storage.insert(Variant::Original, "a", "image/png", b"original");
+        storage.insert(thumbnail(), "a", "image/webp", b"thumbnail");
+        let original = attributes::new().with_dimensions(640, 480).with_size(2048);
+        let formatted = attributes::new()
+            .with_dimensions(300, 225)
+            .with_size(512)
+            .with_blurhash(String::from("LEHV6nWB2yk8pyo0adR*.7kCMdnj"))
+            .with_palette(vec![String::from("#336699"), String::from("#ffffff")]);
+        storage.insert_attributes(Variant::Original, "a", original.clone());
+        storage.insert_attributes(thumbnail(), "a", formatted.clone());
+
+        let image = get_image
+            .execute(String::from("a"), thumbnail())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            image,
+            image::new(
+                String::from("a"),
+                FakeStorage::url(&Variant::Original, "a"),
+                String::from("image/png"),
+                FakeStorage::url(&thumbnail(), "a"),
+                String::from("image/webp"),
+            )
+            .with_attributes(&original, &formatted)
+        );
+        let json = serde_json::to_value(&image).unwrap();
+        assert_eq!(json["dominant_color"], "#336699");
+        assert_eq!(json["palette"][1], "#ffffff");
+    }
+
+    #[tokio::test]
+    async fn returns_poster_of_transcoded_variant() {
+        let (storage, get_image) = setup();
+        let poster = thumbnail().poster().unwrap();
+        storage.insert(Variant::Original, "a", "image/gif", b"original");
+        storage.insert(thumbnail(), "a", "video/mp4", b"thumbnail");
+        storage.insert(poster.clone(), "a", "image/webp", b"poster");
+
+        let image = get_image
+            .execute(String::from("a"), thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            image,
+            Some(
+                image::new(
+                    String::from("a"),
+                    FakeStorage::url(&Variant::Original, "a"),
+                    String::from("image/gif"),
+                    FakeStorage::url(&thumbnail(), "a"),
+                    String::from("video/mp4"),
+                )
+                .with_poster(
+                    FakeStorage::url(&poster, "a"),
+                    String::from("image/webp"),
+                    &attributes::new()
+                )
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn returns_none_when_variant_is_missing() {
+        let (storage, get_image) = setup();
+        storage.insert(Variant::Original, "a", "image/png", b"original");
+
+        let image = get_image
+            .execute(String::from("a"), thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(image, None);
+    }
+
+    #[tokio::test]
+    async fn returns_none_when_original_is_missing() {
+        let (storage, get_image) = setup();
+        storage.insert(thumbnail(), "a", "image/webp", b"thumbnail");
+
+        let image = get_image
+            .execute(String::from("a"), thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(image, None);
+    }
+
+    #[tokio::test]
+    async fn returns_none_when_neither_exists() {
+        let (_, get_image) = setup();
+
+        let image = get_image
+            .execute(String::from("a"), thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(image, None);
+    }
+
+    #[tokio::test]
+    async fn errors_when_original_lookup_fails() {
+        let (storage, get_image) = setup();
+        storage.insert(thumbnail(), "a", "image/webp", b"thumbnail");
+        storage.fail_get(Variant::Original);
+
+        let result = get_image.execute(String::from("a"), thumbnail()).await;
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("could not check if original exists"));
+    }
+
+    #[tokio::test]
+    async fn errors_when_variant_lookup_fails() {
+        let (storage, get_image) = setup();
+        storage.insert(Variant::Original, "a", "image/png", b"original");
+        storage.fail_get(thumbnail());
+
+        let result = get_image.execute(String::from("a"), thumbnail()).await;
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("could not check if thumbnail exists"));
+    }
 }
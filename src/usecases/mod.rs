@@ -1,5 +1,10 @@
 pub mod clean_videos;
+pub mod delete_image;
+#[cfg(test)]
+pub mod fakes;
 pub mod gateways;
 pub mod get_image;
+pub mod similar_images;
+pub mod transform_image;
 pub mod upload_avatar;
 pub mod upload_image;
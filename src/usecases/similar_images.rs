@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use crate::{
+    common::variant::Variant,
+    entities::similar::{self, Similar},
+};
+
+use super::{gateways::Index, get_image::GetImage};
+
+// bounds the lookups a single request makes when many images are alike
+const MAX_SIMILAR: usize = 50;
+
+pub struct SimilarImages {
+    index: Arc<dyn Index>,
+    get_image: Arc<GetImage>,
+}
+
+pub fn new(index: Arc<dyn Index>, get_image: Arc<GetImage>) -> SimilarImages {
+    SimilarImages { index, get_image }
+}
+
+// General idea:
+// - look up the fingerprint of the image, none if it was never indexed
+// - find other fingerprints within the distance, closest first
+// - return the images that still exist in the requested variant
+impl SimilarImages {
+    pub async fn execute(
+        &self,
+        file_name: String,
+        variant: Variant,
+        distance: u32,
+    ) -> Result<Option<Vec<Similar>>> {
+        let fingerprint = match self
+            .index
+            .get(file_name.clone())
+            .await
+            .context("could not get fingerprint")?
+        {
+            Some(fingerprint) => fingerprint,
+            None => return Ok(None),
+        };
+
+        let candidates = self
+            .index
+            .similar(fingerprint, distance)
+            .await
+            .context("could not find similar fingerprints")?;
+
+        let mut similar = Vec::new();
+
+        for (other, distance) in candidates
+            .into_iter()
+            .filter(|(other, _)| *other != file_name)
+            .take(MAX_SIMILAR)
+        {
+            // deleted images and images without this variant are skipped
+            if let Some(image) = self
+                .get_image
+                .execute(other, variant.clone())
+                .await
+                .context("could not get similar image")?
+            {
+                similar.push(similar::new(image, distance));
+            }
+        }
+
+        Ok(Some(similar))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usecases::{
+        self,
+        fakes::{thumbnail, FakeIndex, FakeStorage},
+    };
+
+    struct Setup {
+        storage: Arc<FakeStorage>,
+        index: Arc<FakeIndex>,
+        similar_images: SimilarImages,
+    }
+
+    fn setup() -> Setup {
+        let storage = FakeStorage::new();
+        let index = FakeIndex::new();
+        let get_image = Arc::new(usecases::get_image::new(storage.clone()));
+        let similar_images = new(index.clone(), get_image);
+
+        Setup {
+            storage,
+            index,
+            similar_images,
+        }
+    }
+
+    async fn stored(setup: &Setup, file_name: &str, fingerprint: u64) {
+        setup
+            .storage
+            .insert(Variant::Original, file_name, "image/png", b"original");
+        setup
+            .storage
+            .insert(thumbnail(), file_name, "image/webp", b"thumbnail");
+        setup
+            .index
+            .insert(file_name.to_string(), fingerprint)
+            .await
+            .unwrap();
+    }
+
+    fn names(similar: Vec<Similar>) -> Vec<(String, u32)> {
+        similar
+            .into_iter()
+            .map(|similar| {
+                let json = serde_json::to_value(similar).unwrap();
+                (
+                    json["file_name"].as_str().unwrap().to_string(),
+                    json["distance"].as_u64().unwrap() as u32,
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn returns_other_images_within_distance_closest_first() {
+        let setup = setup();
+        stored(&setup, "a", 0b0000).await;
+        stored(&setup, "b", 0b0011).await;
+        stored(&setup, "c", 0b0001).await;
+        stored(&setup, "d", 0b1111).await;
+
+        let similar = setup
+            .similar_images
+            .execute(String::from("a"), thumbnail(), 2)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            names(similar),
+            vec![(String::from("c"), 1), (String::from("b"), 2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_images_that_no_longer_exist() {
+        let setup = setup();
+        stored(&setup, "a", 0).await;
+        setup.index.insert(String::from("b"), 0).await.unwrap();
+
+        let similar = setup
+            .similar_images
+            .execute(String::from("a"), thumbnail(), 0)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(similar.is_empty());
+    }
+
+    #[tokio::test]
+    async fn returns_none_when_image_is_not_indexed() {
+        let setup = setup();
+
+        let similar = setup
+            .similar_images
+            .execute(String::from("a"), thumbnail(), 10)
+            .await
+            .unwrap();
+
+        assert!(similar.is_none());
+    }
+
+    #[tokio::test]
+    async fn errors_when_index_fails() {
+        let setup = setup();
+        setup.index.fail();
+
+        let result = setup
+            .similar_images
+            .execute(String::from("a"), thumbnail(), 10)
+            .await;
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+
+use crate::common::{attributes, format::Format, profile::Profile, variant::Variant};
+
+use super::gateways::{Images, Storage};
+
+pub struct TransformImage {
+    storage: Arc<dyn Storage>,
+    images: Arc<dyn Images>,
+}
+
+pub fn new(storage: Arc<dyn Storage>, images: Arc<dyn Images>) -> TransformImage {
+    TransformImage { storage, images }
+}
+
+// General idea:
+// - return the cached transform if it was already derived
+// - otherwise load the original and format it with the requested profile
+// - cache the result under a key derived from the profile
+impl TransformImage {
+    pub async fn execute(
+        &self,
+        file_name: String,
+        profile: Profile,
+    ) -> Result<Option<(Vec<u8>, String)>> {
+        let variant = Variant::Transform(profile);
+
+        match self
+            .storage
+            .download(variant.clone(), file_name.clone())
+            .await
+        {
+            Ok(Some(cached)) => return Ok(Some(cached)),
+            Ok(None) => {}
+            Err(e) => bail!("could not check if transform exists: {}", e),
+        };
+
+        let original = match self
+            .storage
+            .download(Variant::Original, file_name.clone())
+            .await
+            .context("could not download original")?
+        {
+            Some((original, _)) => original,
+            None => return Ok(None),
+        };
+
+        let input_format = Format::infer(&original).context("could not infer format")?;
+
+        if !matches!(
+            input_format,
+            Format::Jpeg | Format::Png | Format::WebP | Format::Svg
+        ) {
+            bail!("can not transform {:?}", input_format);
+        }
+
+        let (transformed, output_format) = self
+            .images
+            .format(&original, variant.clone(), input_format)
+            .await
+            .context("could not format image")?;
+
+        // the transform can always be derived again so a failed cache write is not fatal
+        if let Err(e) = self
+            .storage
+            .upload(
+                file_name,
+                variant,
+                output_format.content_type(),
+                transformed.clone(),
+                attributes::new(),
+            )
+            .await
+        {
+            tracing::warn!("could not cache transform: {}", e);
+        }
+
+        Ok(Some((transformed, output_format.content_type())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::profile::{self, Fit},
+        usecases::fakes::{FakeImages, FakeStorage, GIF, PNG},
+    };
+
+    struct Setup {
+        storage: Arc<FakeStorage>,
+        images: Arc<FakeImages>,
+        transform_image: TransformImage,
+    }
+
+    fn setup() -> Setup {
+        let storage = FakeStorage::new();
+        let images = FakeImages::new();
+        let transform_image = new(storage.clone(), images.clone());
+
+        Setup {
+            storage,
+            images,
+            transform_image,
+        }
+    }
+
+    fn profile() -> Profile {
+        profile::transform(64, 32, Fit::Cover, Format::WebP, 70)
+    }
+
+    #[tokio::test]
+    async fn formats_and_caches_original() {
+        let setup = setup();
+        setup
+            .storage
+            .insert(Variant::Original, "a", "image/png", PNG);
+
+        let result = setup
+            .transform_image
+            .execute(String::from("a"), profile())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            result,
+            Some((b"formatted image".to_vec(), String::from("image/webp")))
+        );
+        assert_eq!(
+            setup.images.calls(),
+            vec![(Variant::Transform(profile()), Format::Png)]
+        );
+        assert_eq!(
+            setup.storage.object(Variant::Transform(profile()), "a"),
+            Some((String::from("image/webp"), b"formatted image".to_vec()))
+        );
+    }
+
+    #[tokio::test]
+    async fn returns_cached_transform() {
+        let setup = setup();
+        setup
+            .storage
+            .insert(Variant::Original, "a", "image/png", PNG);
+        setup
+            .storage
+            .insert(Variant::Transform(profile()), "a", "image/webp", b"cached");
+
+        let result = setup
+            .transform_image
+            .execute(String::from("a"), profile())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            result,
+            Some((b"cached".to_vec(), String::from("image/webp")))
+        );
+        assert!(setup.images.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn returns_none_when_original_is_missing() {
+        let setup = setup();
+
+        let result = setup
+            .transform_image
+            .execute(String::from("a"), profile())
+            .await
+            .unwrap();
+
+        assert_eq!(result, None);
+    }
+
+    #[tokio::test]
+    async fn returns_transform_when_cache_write_fails() {
+        let setup = setup();
+        setup
+            .storage
+            .insert(Variant::Original, "a", "image/png", PNG);
+        setup.storage.fail_upload(Variant::Transform(profile()));
+
+        let result = setup
+            .transform_image
+            .execute(String::from("a"), profile())
+            .await
+            .unwrap();
+
+        assert!(result.is_some());
+    }
+
+    #[tokio::test]
+    async fn rejects_animated_originals() {
+        let setup = setup();
+        setup
+            .storage
+            .insert(Variant::Original, "a", "image/gif", GIF);
+
+        let result = setup
+            .transform_image
+            .execute(String::from("a"), profile())
+            .await;
+
+        assert!(result.is_err());
+        assert!(setup.images.calls().is_empty());
+    }
+}
@@ -1,9 +1,10 @@
 use anyhow::{bail, Context, Result};
-use hex;
-use sha2::{Digest, Sha256};
 use std::sync::Arc;
 
-use crate::{common::variant::Variant, entities::image::Image};
+use crate::{
+    common::{hash, variant::Variant},
+    entities::image::Image,
+};
 
 use super::{gateways::Web, get_image::GetImage, upload_image::UploadImage};
 
@@ -11,17 +12,20 @@ pub struct UploadAvatar {
     web: Arc<dyn Web>,
     upload_image: Arc<UploadImage>,
     get_image: Arc<GetImage>,
+    variant: Variant,
 }
 
 pub fn new(
     web: Arc<dyn Web>,
     upload_image: Arc<UploadImage>,
     get_image: Arc<GetImage>,
+    variant: Variant,
 ) -> UploadAvatar {
     UploadAvatar {
         web,
         upload_image,
         get_image,
+        variant,
     }
 }
 
@@ -33,11 +37,11 @@ pub fn new(
 // - download image from url and format/upload
 impl UploadAvatar {
     pub async fn execute(&self, url: String, is_nft: bool) -> Result<Image> {
-        let file_name = self.hash(url.clone());
+        let file_name = hash::sha256(&url);
 
         match self
             .get_image
-            .execute(file_name.clone(), Variant::Avatar)
+            .execute(file_name.clone(), self.variant.clone())
             .await
         {
             Ok(Some(image)) => {
@@ -66,14 +70,147 @@ impl UploadAvatar {
             .context("could not get image data")?;
 
         self.upload_image
-            .execute(file_name, &data, Variant::Avatar)
+            .execute(file_name, &data, self.variant.clone())
             .await
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::format::Format,
+        usecases::{
+            self,
+            fakes::{avatar, FakeImages, FakeIndex, FakeStorage, FakeVideo, FakeWeb, PNG},
+        },
+    };
+
+    struct Setup {
+        storage: Arc<FakeStorage>,
+        images: Arc<FakeImages>,
+        web: Arc<FakeWeb>,
+        upload_avatar: UploadAvatar,
+    }
+
+    fn setup() -> Setup {
+        let storage = FakeStorage::new();
+        let images = FakeImages::new();
+        let web = FakeWeb::new();
+        let upload_image = Arc::new(usecases::upload_image::new(
+            storage.clone(),
+            images.clone(),
+            FakeVideo::new(),
+            FakeIndex::new(),
+        ));
+        let get_image = Arc::new(usecases::get_image::new(storage.clone()));
+        let upload_avatar = new(web.clone(), upload_image, get_image, avatar());
+
+        Setup {
+            storage,
+            images,
+            web,
+            upload_avatar,
+        }
+    }
+
+    #[tokio::test]
+    async fn returns_existing_avatar_without_fetching() {
+        let setup = setup();
+        let url = String::from("https://example.com/a.png");
+        let file_name = hash::sha256(&url);
+        setup
+            .storage
+            .insert(Variant::Original, &file_name, "image/png", PNG);
+        setup
+            .storage
+            .insert(avatar(), &file_name, "image/webp", b"avatar");
+
+        setup.upload_avatar.execute(url, false).await.unwrap();
+
+        assert_eq!(
+            setup.storage.gets(),
+            vec![
+                (Variant::Original, file_name.clone()),
+                (avatar(), file_name)
+            ]
+        );
+        assert!(setup.web.requests().is_empty());
+        assert!(setup.storage.uploads().is_empty());
+    }
+
+    #[tokio::test]
+    async fn downloads_and_uploads_plain_url() {
+        let setup = setup();
+        let url = String::from("https://example.com/a.png");
+        let file_name = hash::sha256(&url);
+        setup.web.insert_image(&url, PNG);
+
+        setup
+            .upload_avatar
+            .execute(url.clone(), false)
+            .await
+            .unwrap();
+
+        assert_eq!(setup.web.requests(), vec![url]);
+        assert_eq!(setup.images.calls(), vec![(avatar(), Format::Png)]);
+        assert_eq!(
+            setup.storage.object(Variant::Original, &file_name),
+            Some((String::from("image/png"), PNG.to_vec()))
+        );
+        assert!(setup.storage.object(avatar(), &file_name).is_some());
+    }
+
+    #[tokio::test]
+    async fn resolves_nft_metadata_before_downloading() {
+        let setup = setup();
+        let url = String::from("https://example.com/metadata/1");
+        let image_url = String::from("https://example.com/1.png");
+        let file_name = hash::sha256(&url);
+        setup.web.insert_nft(&url, &image_url);
+        setup.web.insert_image(&image_url, PNG);
+
+        setup
+            .upload_avatar
+            .execute(url.clone(), true)
+            .await
+            .unwrap();
+
+        assert_eq!(setup.web.requests(), vec![url, image_url]);
+        assert!(setup.storage.object(avatar(), &file_name).is_some());
+    }
+
+    #[tokio::test]
+    async fn errors_when_nft_metadata_is_unavailable() {
+        let setup = setup();
+        let url = String::from("https://example.com/metadata/1");
+
+        let result = setup.upload_avatar.execute(url, true).await;
+
+        assert!(result.is_err());
+        assert!(setup.storage.uploads().is_empty());
+    }
+
+    #[tokio::test]
+    async fn errors_when_image_is_unavailable() {
+        let setup = setup();
+        let url = String::from("https://example.com/a.png");
+
+        let result = setup.upload_avatar.execute(url, false).await;
+
+        assert!(result.is_err());
+        assert!(setup.storage.uploads().is_empty());
+    }
+
+    #[tokio::test]
+    async fn errors_when_existing_avatar_lookup_fails() {
+        let setup = setup();
+        let url = String::from("https://example.com/a.png");
+        setup.storage.fail_get(avatar());
+
+        let result = setup.upload_avatar.execute(url, false).await;
 
-    fn hash(&self, input: String) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(input);
-        let result = hasher.finalize();
-        hex::encode(result)
+        assert!(result.is_err());
+        assert!(setup.web.requests().is_empty());
     }
 }
@@ -2,74 +2,676 @@ use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
 
-use super::gateways::{Images, Storage, Video};
+use super::{
+    gateways::{Formatted, Images, Index, Storage, Video},
+    get_image::{self, GetImage},
+};
 use crate::{
-    common::{format::Format, variant::Variant},
+    common::{
+        appearance::Appearance,
+        attributes::{self, Attributes},
+        format::{self, Format},
+        hash,
+        variant::Variant,
+    },
     entities::image::{self, Image},
 };
 
+// The placeholder, colors and fingerprint describe how the variant looks, so they come
+// from the variant itself when that is a still image and from its poster when it is
+// video. Like the poster they are nice to haves and failing to compute them only warns.
+fn preview<'a>(
+    (original, input_format): (&'a [u8], &'a Format),
+    (thumbnail, output_format): (&'a [u8], &'a Format),
+    poster: Option<&'a Formatted>,
+) -> Option<(&'a [u8], &'a Format)> {
+    match (output_format, poster) {
+        (Format::Jpeg | Format::Png | Format::WebP, _) => Some((thumbnail, output_format)),
+        (_, Some((poster, poster_format))) => Some((poster.as_slice(), poster_format)),
+        // avif can't be decoded, but the original looks the same
+        (Format::Avif, None) => Some((original, input_format)),
+        _ => None,
+    }
+}
+
 pub struct UploadImage {
     storage: Arc<dyn Storage>,
     images: Arc<dyn Images>,
     video: Arc<dyn Video>,
+    index: Arc<dyn Index>,
+    get_image: GetImage,
 }
 
 pub fn new(
     storage: Arc<dyn Storage>,
     images: Arc<dyn Images>,
     video: Arc<dyn Video>,
+    index: Arc<dyn Index>,
 ) -> UploadImage {
     UploadImage {
+        get_image: get_image::new(storage.clone()),
         storage,
         images,
         video,
+        index,
     }
 }
 
 impl UploadImage {
+    // Names the upload after a hash of its bytes, so uploading the same file again
+    // returns the image stored the first time instead of processing it again. An
+    // original stored without this variant is processed as usual. Also tells whether
+    // the image was stored by this call.
+    pub async fn execute_deduplicated(
+        &self,
+        data: &[u8],
+        variant: Variant,
+    ) -> Result<(Image, bool)> {
+        let file_name = hash::sha256(data);
+
+        match self
+            .get_image
+            .execute(file_name.clone(), variant.clone())
+            .await
+        {
+            Ok(Some(image)) => {
+                tracing::info!("image already exists");
+                return Ok((image, false));
+            }
+            Ok(None) => {}
+            Err(e) => bail!("could not check if image exists: {}", e),
+        };
+
+        let image = self.execute(file_name, data, variant).await?;
+
+        Ok((image, true))
+    }
+
     pub async fn execute(&self, file_name: String, data: &[u8], variant: Variant) -> Result<Image> {
         let input_format = Format::infer(data).context("could not infer format")?;
 
+        let original = self
+            .images
+            .sanitize(data, input_format.clone())
+            .await
+            .context("could not sanitize image")?;
+
         let result = match input_format {
-            Format::Jpeg | Format::Png | Format::WebP => {
-                self.images
-                    .format(data, variant.clone(), input_format.clone())
+            // checked on the upload itself so sanitizing can never change how it is processed
+            Format::WebP if format::is_animated_webp(data) => {
+                self.video
+                    .format(&original, variant.clone(), input_format.clone())
                     .await
             }
+            Format::Jpeg | Format::Png | Format::WebP | Format::Svg => self
+                .images
+                .format(&original, variant.clone(), input_format.clone())
+                .await
+                .map(|formatted| (formatted, None)),
             Format::Gif | Format::Mp4 => {
                 self.video
-                    .format(data, variant.clone(), input_format.clone())
+                    .format(&original, variant.clone(), input_format.clone())
                     .await
             }
+            Format::Avif => bail!("unsupported input format: {:?}", input_format),
         };
 
-        let (thumbnail, output_format) = result.context("could not format image")?;
+        let ((thumbnail, output_format), poster) = result.context("could not format image")?;
+
+        let preview = preview(
+            (&original, &input_format),
+            (&thumbnail, &output_format),
+            poster.as_ref().map(|(_, poster)| poster),
+        );
+
+        let (appearance, original_attributes, thumbnail_attributes, poster_attributes) = tokio::join!(
+            self.appearance(preview),
+            self.describe(&original, &input_format),
+            self.describe(&thumbnail, &output_format),
+            async {
+                match &poster {
+                    Some((_, (poster, poster_format))) => {
+                        Some(self.describe(poster, poster_format).await)
+                    }
+                    None => None,
+                }
+            },
+        );
+        let thumbnail_attributes = match &appearance {
+            Some(appearance) => thumbnail_attributes
+                .with_blurhash(appearance.blurhash())
+                .with_palette(appearance.palette()),
+            None => thumbnail_attributes,
+        };
 
         let (original_result, thumbnail_result) = tokio::join!(
             self.storage.upload(
                 file_name.to_string(),
                 Variant::Original,
                 input_format.content_type(),
-                data.to_vec(),
+                original,
+                original_attributes.clone(),
             ),
             self.storage.upload(
                 file_name.to_string(),
                 variant,
                 output_format.content_type(),
-                thumbnail
+                thumbnail,
+                thumbnail_attributes.clone(),
             ),
         );
 
-        match (original_result, thumbnail_result) {
-            (Ok(original_url), Ok(thumbnail_url)) => Ok(image::new(
+        let image = match (original_result, thumbnail_result) {
+            (Ok(original_url), Ok(thumbnail_url)) => image::new(
                 file_name.to_string(),
                 original_url,
                 input_format.content_type(),
                 thumbnail_url,
                 output_format.content_type(),
-            )),
+            )
+            .with_attributes(&original_attributes, &thumbnail_attributes),
             (Err(e), _) => bail!("could not upload original: {}", e),
             (_, Err(e)) => bail!("could not upload thumbnail: {}", e),
+        };
+
+        // for finding near duplicates later on
+        if let Some(appearance) = appearance {
+            if let Err(e) = self
+                .index
+                .insert(file_name.clone(), appearance.fingerprint())
+                .await
+            {
+                tracing::warn!("could not index image: {}", e);
+            }
         }
+
+        let ((poster_variant, (poster, poster_format)), poster_attributes) =
+            match poster.zip(poster_attributes) {
+                Some(poster) => poster,
+                None => return Ok(image),
+            };
+
+        match self
+            .storage
+            .upload(
+                file_name,
+                poster_variant,
+                poster_format.content_type(),
+                poster,
+                poster_attributes.clone(),
+            )
+            .await
+        {
+            Ok(poster_url) => {
+                Ok(image.with_poster(poster_url, poster_format.content_type(), &poster_attributes))
+            }
+            Err(e) => {
+                tracing::warn!("could not upload poster: {}", e);
+                Ok(image)
+            }
+        }
+    }
+
+    // placeholder, colors and fingerprint of the variant, see preview
+    async fn appearance(&self, preview: Option<(&[u8], &Format)>) -> Option<Appearance> {
+        let (data, format) = preview?;
+
+        match self.images.appearance(data, format.clone()).await {
+            Ok(appearance) => Some(appearance),
+            Err(e) => {
+                tracing::warn!("could not compute appearance: {}", e);
+                None
+            }
+        }
+    }
+
+    // Layout details for the response and for later lookups. They are read from the
+    // stored bytes, so failing to read them only leaves them out.
+    async fn describe(&self, data: &[u8], format: &Format) -> Attributes {
+        let described = match format {
+            Format::Gif | Format::Mp4 => self.video.describe(data, format.clone()).await,
+            Format::WebP if format::is_animated_webp(data) => {
+                self.video.describe(data, format.clone()).await
+            }
+            _ => self.images.describe(data, format.clone()).await,
+        };
+
+        let attributes = described.unwrap_or_else(|e| {
+            tracing::warn!("could not describe {:?}: {}", format, e);
+            attributes::new()
+        });
+
+        attributes.with_size(data.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::profile::Profile,
+        usecases::fakes::{
+            thumbnail, FakeImages, FakeIndex, FakeStorage, FakeVideo, ANIMATED_WEBP, GIF, PNG,
+        },
+    };
+
+    struct Setup {
+        storage: Arc<FakeStorage>,
+        images: Arc<FakeImages>,
+        video: Arc<FakeVideo>,
+        index: Arc<FakeIndex>,
+        upload_image: UploadImage,
+    }
+
+    // what the fake gateways describe, plus the size of the bytes described
+    fn still(size: usize) -> Attributes {
+        attributes::new()
+            .with_dimensions(100, 50)
+            .with_size(size as u64)
+    }
+
+    fn palette() -> Vec<String> {
+        vec![String::from("#ff0000"), String::from("#0000ff")]
+    }
+
+    fn animation(size: usize) -> Attributes {
+        attributes::new()
+            .with_dimensions(300, 200)
+            .with_duration(2.0)
+            .with_frame_rate(16.0)
+            .with_size(size as u64)
+    }
+
+    fn setup() -> Setup {
+        let storage = FakeStorage::new();
+        let images = FakeImages::new();
+        let video = FakeVideo::new();
+        let index = FakeIndex::new();
+        let upload_image = new(
+            storage.clone(),
+            images.clone(),
+            video.clone(),
+            index.clone(),
+        );
+
+        Setup {
+            storage,
+            images,
+            video,
+            index,
+            upload_image,
+        }
+    }
+
+    #[tokio::test]
+    async fn formats_images_with_images_gateway() {
+        let setup = setup();
+
+        let image = setup
+            .upload_image
+            .execute(String::from("a"), PNG, thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            image,
+            image::new(
+                String::from("a"),
+                FakeStorage::url(&Variant::Original, "a"),
+                String::from("image/png"),
+                FakeStorage::url(&thumbnail(), "a"),
+                String::from("image/webp"),
+            )
+            .with_attributes(
+                &still(PNG.len()),
+                &still(b"formatted image".len())
+                    .with_blurhash(String::from("blurhash of formatted image"))
+                    .with_palette(palette())
+            )
+        );
+        assert_eq!(setup.images.calls(), vec![(thumbnail(), Format::Png)]);
+        assert!(setup.video.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn formats_animations_with_video_gateway() {
+        let setup = setup();
+
+        setup
+            .upload_image
+            .execute(String::from("a"), GIF, thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            setup.video.calls(),
+            vec![
+                (thumbnail(), Format::Gif),
+                (thumbnail().poster().unwrap(), Format::Gif)
+            ]
+        );
+        assert!(setup.images.calls().is_empty());
+        assert_eq!(
+            setup.storage.object(thumbnail(), "a"),
+            Some((String::from("video/mp4"), b"formatted video".to_vec()))
+        );
+    }
+
+    #[tokio::test]
+    async fn formats_animated_webp_with_video_gateway() {
+        let setup = setup();
+
+        setup
+            .upload_image
+            .execute(String::from("a"), ANIMATED_WEBP, thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            setup.video.calls(),
+            vec![
+                (thumbnail(), Format::WebP),
+                (thumbnail().poster().unwrap(), Format::WebP)
+            ]
+        );
+        assert!(setup.images.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn skips_poster_for_animated_webp_output() {
+        let setup = setup();
+        let variant = Variant::Profile(
+            serde_json::from_value::<Profile>(serde_json::json!({
+                "name": "thumbnail",
+                "width": 300,
+                "height": 300,
+                "video": { "format": "webp" },
+            }))
+            .unwrap(),
+        );
+
+        let image = setup
+            .upload_image
+            .execute(String::from("a"), GIF, variant.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(setup.video.calls(), vec![(variant.clone(), Format::Gif)]);
+        assert_eq!(
+            image,
+            image::new(
+                String::from("a"),
+                FakeStorage::url(&Variant::Original, "a"),
+                String::from("image/gif"),
+                FakeStorage::url(&variant, "a"),
+                String::from("image/webp"),
+            )
+            .with_attributes(
+                &animation(GIF.len()),
+                // webp is previewed from itself rather than a poster
+                &still(b"formatted video".len())
+                    .with_blurhash(String::from("blurhash of formatted video"))
+                    .with_palette(palette())
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn stores_poster_of_animations() {
+        let setup = setup();
+        let poster = thumbnail().poster().unwrap();
+
+        let image = setup
+            .upload_image
+            .execute(String::from("a"), GIF, thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            image,
+            image::new(
+                String::from("a"),
+                FakeStorage::url(&Variant::Original, "a"),
+                String::from("image/gif"),
+                FakeStorage::url(&thumbnail(), "a"),
+                String::from("video/mp4"),
+            )
+            .with_poster(
+                FakeStorage::url(&poster, "a"),
+                String::from("image/webp"),
+                &still(b"poster".len())
+            )
+            .with_attributes(
+                &animation(GIF.len()),
+                &animation(b"formatted video".len())
+                    .with_blurhash(String::from("blurhash of poster"))
+                    .with_palette(palette())
+            )
+        );
+        assert_eq!(
+            setup.storage.object(poster, "a"),
+            Some((String::from("image/webp"), b"poster".to_vec()))
+        );
+    }
+
+    #[tokio::test]
+    async fn stores_placeholder_with_thumbnail() {
+        let setup = setup();
+
+        setup
+            .upload_image
+            .execute(String::from("a"), PNG, thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            setup.storage.attributes(thumbnail(), "a"),
+            Some(
+                still(b"formatted image".len())
+                    .with_blurhash(String::from("blurhash of formatted image"))
+                    .with_palette(palette())
+            )
+        );
+        assert_eq!(
+            setup.storage.attributes(Variant::Original, "a"),
+            Some(still(PNG.len()))
+        );
+    }
+
+    #[tokio::test]
+    async fn uploads_without_poster_when_extraction_fails() {
+        let setup = setup();
+        setup.video.fail_poster();
+
+        let image = setup
+            .upload_image
+            .execute(String::from("a"), GIF, thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            image,
+            image::new(
+                String::from("a"),
+                FakeStorage::url(&Variant::Original, "a"),
+                String::from("image/gif"),
+                FakeStorage::url(&thumbnail(), "a"),
+                String::from("video/mp4"),
+            )
+            .with_attributes(&animation(GIF.len()), &animation(b"formatted video".len()))
+        );
+        assert_eq!(setup.storage.uploads().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn stores_sanitized_svg_original() {
+        let setup = setup();
+
+        setup
+            .upload_image
+            .execute(String::from("a"), b"<svg><script/></svg>", thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(setup.images.calls(), vec![(thumbnail(), Format::Svg)]);
+        assert_eq!(
+            setup.storage.object(Variant::Original, "a"),
+            Some((String::from("image/svg+xml"), b"sanitized svg".to_vec()))
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_formats() {
+        let setup = setup();
+
+        let result = setup
+            .upload_image
+            .execute(String::from("a"), b"not an image", thumbnail())
+            .await;
+
+        assert!(result.is_err());
+        assert!(setup.storage.uploads().is_empty());
+    }
+
+    #[tokio::test]
+    async fn does_not_upload_when_formatting_fails() {
+        let setup = setup();
+        setup.images.fail();
+
+        let result = setup
+            .upload_image
+            .execute(String::from("a"), PNG, thumbnail())
+            .await;
+
+        assert!(result.is_err());
+        assert!(setup.storage.uploads().is_empty());
+    }
+
+    #[tokio::test]
+    async fn does_not_upload_when_transcoding_fails() {
+        let setup = setup();
+        setup.video.fail();
+
+        let result = setup
+            .upload_image
+            .execute(String::from("a"), GIF, thumbnail())
+            .await;
+
+        assert!(result.is_err());
+        assert!(setup.storage.uploads().is_empty());
+    }
+
+    #[tokio::test]
+    async fn errors_when_upload_fails() {
+        let setup = setup();
+        setup.storage.fail_upload(Variant::Original);
+
+        let result = setup
+            .upload_image
+            .execute(String::from("a"), PNG, thumbnail())
+            .await;
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("could not upload original"));
+    }
+
+    #[tokio::test]
+    async fn names_deduplicated_uploads_after_their_content() {
+        let setup = setup();
+
+        setup
+            .upload_image
+            .execute_deduplicated(PNG, thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            setup.storage.uploads(),
+            vec![
+                (Variant::Original, hash::sha256(PNG)),
+                (thumbnail(), hash::sha256(PNG))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn returns_existing_image_for_duplicate_upload() {
+        let setup = setup();
+        let (uploaded, created) = setup
+            .upload_image
+            .execute_deduplicated(PNG, thumbnail())
+            .await
+            .unwrap();
+        assert!(created);
+
+        let (image, created) = setup
+            .upload_image
+            .execute_deduplicated(PNG, thumbnail())
+            .await
+            .unwrap();
+
+        assert!(!created);
+        assert_eq!(image, uploaded);
+        assert_eq!(setup.images.calls().len(), 1);
+        assert_eq!(setup.storage.uploads().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn errors_when_duplicate_lookup_fails() {
+        let setup = setup();
+        setup.storage.fail_get(Variant::Original);
+
+        let result = setup
+            .upload_image
+            .execute_deduplicated(PNG, thumbnail())
+            .await;
+
+        assert!(result.is_err());
+        assert!(setup.storage.uploads().is_empty());
+    }
+
+    #[tokio::test]
+    async fn indexes_fingerprint_of_thumbnail() {
+        let setup = setup();
+
+        setup
+            .upload_image
+            .execute(String::from("a"), PNG, thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            setup.index.fingerprint("a"),
+            Some(b"formatted image".len() as u64)
+        );
+    }
+
+    #[tokio::test]
+    async fn indexes_fingerprint_of_poster() {
+        let setup = setup();
+
+        setup
+            .upload_image
+            .execute(String::from("a"), GIF, thumbnail())
+            .await
+            .unwrap();
+
+        assert_eq!(setup.index.fingerprint("a"), Some(b"poster".len() as u64));
+    }
+
+    #[tokio::test]
+    async fn uploads_when_indexing_fails() {
+        let setup = setup();
+        setup.index.fail();
+
+        let result = setup
+            .upload_image
+            .execute(String::from("a"), PNG, thumbnail())
+            .await;
+
+        assert!(result.is_ok());
+        assert_eq!(setup.storage.uploads().len(), 2);
     }
 }